@@ -0,0 +1,128 @@
+use std::{collections::HashMap, marker::PhantomData, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use reqwest::header::CONTENT_TYPE;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{error, info};
+
+use crate::{dtos::GetSelfRoute, metrics::CALLBACKS, models::{CallbackStatus, JobModel}, nats::subscribe::{Delivery, IHeartbeat, IWorkerService, WorkError}, persistence::{IApiKeyPersistence, IJobPersistence}, util::{cipher::Cipher, signature::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER}}};
+
+pub struct CallbackService<InputType, ResultType> {
+    pub job_persistence: Arc<dyn IJobPersistence>,
+    pub api_key_persistence: Arc<dyn IApiKeyPersistence>,
+    pub client: reqwest::Client,
+    pub max_age: Duration,
+    pub secret: Option<String>,
+    pub headers_cipher: Option<Arc<Cipher>>,
+    job_type: PhantomData<fn() -> JobModel<InputType, ResultType>>,
+}
+
+impl<InputType, ResultType> CallbackService<InputType, ResultType> {
+    pub fn new(job_persistence: Arc<dyn IJobPersistence>, api_key_persistence: Arc<dyn IApiKeyPersistence>, timeout: Duration, max_age: Duration, secret: Option<String>, headers_cipher: Option<Arc<Cipher>>) -> Self {
+        CallbackService {
+            job_persistence,
+            api_key_persistence,
+            client: reqwest::Client::builder().danger_accept_invalid_certs(true).timeout(timeout).build().unwrap(),
+            max_age,
+            secret,
+            headers_cipher,
+            job_type: PhantomData,
+        }
+    }
+
+    fn callback_headers(&self, sealed: &Option<String>) -> Result<HashMap<String, String>, &'static str> {
+        match (sealed, &self.headers_cipher) {
+            (None, _) => Ok(HashMap::new()),
+            (Some(_), None) => Err("no key for callback headers configured"),
+            (Some(sealed), Some(cipher)) => cipher.open_json(sealed),
+        }
+    }
+
+    /// The callback secret of the api key that created the job takes precedence over the global one.
+    async fn callback_secret(&self, api_key_id: &Option<String>) -> Result<Option<String>, WorkError> {
+        let api_key = match api_key_id {
+            Some(api_key_id) => self.api_key_persistence.get_api_key(api_key_id).await.map_err(|_| WorkError::Retry)?,
+            None => None,
+        };
+        Ok(api_key.and_then(|api_key| api_key.callback_secret).or_else(|| self.secret.clone()))
+    }
+}
+
+#[async_trait::async_trait]
+impl<InputType, ResultType> IWorkerService for CallbackService<InputType, ResultType>
+    where JobModel<InputType, ResultType>: GetSelfRoute + DeserializeOwned, ResultType: Clone + Serialize + Send + Sync, InputType: Serialize + Send + Sync
+{
+    #[tracing::instrument(skip(self, _heartbeat))]
+    async fn work(&self, job_id: &str, delivery: Delivery, _heartbeat: Arc<dyn IHeartbeat>) -> Result<(), WorkError> {
+        let job_model = match self.job_persistence.get(job_id).await.map_err(|_| WorkError::Retry)? {
+            Some(job_model) => job_model,
+            None => return Ok(()),
+        };
+        let mut job_model: JobModel<InputType, ResultType> = serde_json::from_slice(&job_model).map_err(|_| WorkError::NoRetry)?;
+        let (callback_uri, mut callback) = match (&job_model.callback_uri, &job_model.callback) {
+            (Some(callback_uri), Some(callback)) if callback.status == CallbackStatus::Pending => (callback_uri.clone(), callback.clone()),
+            _ => return Ok(()),
+        };
+
+        let headers = match self.callback_headers(&job_model.callback_headers) {
+            Ok(headers) => headers,
+            Err(err) => {
+                error!("Could not read callback headers of '{}': {}", &job_model.id, err);
+                callback.status = CallbackStatus::Failed;
+                callback.last_error = Some(err.to_string());
+                job_model.callback = Some(callback);
+                self.job_persistence.put(&job_model).await.map_err(|_| WorkError::Retry)?;
+                CALLBACKS.with_label_values(&["failed"]).inc();
+                return Err(WorkError::NoRetry);
+            }
+        };
+
+        let secret = self.callback_secret(&job_model.api_key_id).await?;
+        let now = Utc::now();
+        callback.attempts += 1;
+        callback.last_attempt = Some(now);
+        let body = serde_json::to_vec(&job_model.to_dto()).map_err(|_| WorkError::NoRetry)?;
+        let mut request = self.client.post(&callback_uri).header(CONTENT_TYPE, "application/json");
+        for (name, value) in &headers {
+            request = request.header(name, value);
+        }
+        if let Some(secret) = &secret {
+            let timestamp = now.timestamp();
+            request = request.header(TIMESTAMP_HEADER, timestamp).header(SIGNATURE_HEADER, sign(secret, timestamp, &body));
+        }
+        match request.body(body).send().await {
+            Ok(response) => {
+                info!("Send callback '{}' to '{}', with {}", &job_model.id, &callback_uri, response.status());
+                callback.last_status_code = Some(response.status().as_u16());
+                callback.last_error = None;
+                if response.status().is_success() {
+                    callback.status = CallbackStatus::Delivered;
+                }
+            }
+            Err(err) => {
+                info!("Error sending {} time callback '{}' to '{}', because of {}", callback.attempts, &job_model.id, &callback_uri, err);
+                callback.last_status_code = None;
+                callback.last_error = Some(err.to_string());
+            }
+        }
+        let age = now.signed_duration_since(job_model.finished.unwrap_or(job_model.created)).to_std().unwrap_or_default();
+        if callback.status == CallbackStatus::Pending && (age >= self.max_age || delivery.last) {
+            info!("Giving up callback '{}' to '{}' after {} attempts", &job_model.id, &callback_uri, callback.attempts);
+            callback.status = CallbackStatus::Failed;
+        }
+
+        let status = callback.status.clone();
+        job_model.callback = Some(callback);
+        self.job_persistence.put(&job_model).await.map_err(|_| WorkError::Retry)?;
+        CALLBACKS.with_label_values(&[match status {
+            CallbackStatus::Delivered => "delivered",
+            CallbackStatus::Failed => "failed",
+            CallbackStatus::Pending => "retry",
+        }]).inc();
+        match status {
+            CallbackStatus::Delivered => Ok(()),
+            CallbackStatus::Failed => Err(WorkError::NoRetry),
+            CallbackStatus::Pending => Err(WorkError::Retry),
+        }
+    }
+}
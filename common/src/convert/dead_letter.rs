@@ -0,0 +1,48 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::info;
+
+use crate::{dtos::GetSelfRoute, error::{Error, ErrorCode}, models::{JobModel, JobStatus}, nats::{dlq_subscribe::{DLQReason, IDLQWorkerService}, subscribe::WorkError}};
+
+use super::BaseConvertService;
+
+pub struct DeadLetterService<InputType, ResultType> {
+    pub base: Arc<BaseConvertService>,
+    job_type: PhantomData<fn() -> JobModel<InputType, ResultType>>,
+}
+
+impl<InputType, ResultType> DeadLetterService<InputType, ResultType> {
+    pub fn new(base: Arc<BaseConvertService>) -> Self {
+        DeadLetterService {
+            base,
+            job_type: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<InputType, ResultType> IDLQWorkerService for DeadLetterService<InputType, ResultType>
+    where JobModel<InputType, ResultType>: GetSelfRoute + DeserializeOwned, ResultType: Clone + Serialize + Send + Sync, InputType: Serialize + Send + Sync
+{
+    #[tracing::instrument(skip(self))]
+    async fn work(&self, job_id: &str, reason: DLQReason) -> Result<(), &'static str> {
+        let job_model = match self.base.job_persistence.get(job_id).await.map_err(|_| "could not get job")? {
+            Some(job_model) => job_model,
+            None => return Ok(()),
+        };
+        let mut job_model: JobModel<InputType, ResultType> = serde_json::from_slice(&job_model).map_err(|_| "job is not valid json")?;
+        if !matches!(job_model.status, JobStatus::Pending | JobStatus::InProgress) {
+            info!("Job '{}' is already {:?}, nothing to do", job_id, job_model.status);
+            return Ok(());
+        }
+        let err = match &job_model.error {
+            Some(last) => Error::permanent(ErrorCode::DeadLettered, reason.to_string()).with_source(last),
+            None => Error::permanent(ErrorCode::DeadLettered, reason.to_string()),
+        };
+        match self.base.error(&mut job_model, err).await {
+            Err(WorkError::Retry) => Err("could not fail job"),
+            _ => Ok(()),
+        }
+    }
+}
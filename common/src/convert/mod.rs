@@ -1,56 +1,162 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use chrono::Utc;
 use serde::Serialize;
-use tracing::info;
+use tracing::{error, info};
 
-use crate::{persistence::IJobPersistence, models::JobModel, dtos::GetSelfRoute};
+use crate::{error::Error, metrics::JOB_DURATION, persistence::IJobPersistence, models::{CallbackModel, JobModel, JobStatus, JobStatusModel, StoredResult}, nats::{publish::IPublishService, quota::IQuotaService, subscribe::{WorkError, IHeartbeat}}};
+
+mod progress;
+pub use progress::*;
+
+mod dead_letter;
+pub use dead_letter::*;
+
+mod callback;
+pub use callback::*;
 
 pub struct BaseConvertService {
     pub job_persistence: Arc<dyn IJobPersistence>,
+    pub callback_publish_service: Arc<dyn IPublishService>,
+    pub progress_interval: Duration,
+    pub quota_service: Arc<dyn IQuotaService>,
+    pub job_type: &'static str,
 }
 
 impl BaseConvertService {
-    pub async fn ready<'a, InputType, ResultType>(&'a self, job: &mut JobModel<InputType, ResultType>, client: &reqwest::Client, result: ResultType)
-        where JobModel<InputType, ResultType>: GetSelfRoute, ResultType: Clone, JobModel<InputType, ResultType>: Serialize, ResultType: Serialize + Send + Sync, InputType: Serialize + Send + Sync
+    pub async fn start<InputType, ResultType>(&self, job: &mut JobModel<InputType, ResultType>) -> Result<(), WorkError>
+        where ResultType: Serialize + Send + Sync, InputType: Serialize + Send + Sync
+    {
+        let started = Utc::now();
+        self.transition(job, |job| {
+            job.status = JobStatus::InProgress;
+            job.started = Some(started);
+        }).await
+    }
+
+    pub fn progress<InputType, ResultType>(&self, job: &JobModel<InputType, ResultType>, heartbeat: Option<Arc<dyn IHeartbeat>>) -> ProgressReporter
+        where JobModel<InputType, ResultType>: Clone, InputType: Serialize + Send + Sync + 'static, ResultType: Serialize + Send + Sync + 'static
     {
-        job.result = Some(result);
-        let result = self.job_persistence.put(job).await;
-        if let Err(err) = result {
-            self.error(job, client, err).await;
-            return;
+        ProgressReporter::start(job, self.job_persistence.clone(), heartbeat, self.progress_interval)
+    }
+
+    pub async fn ensure_not_cancelled(&self, job_id: &str) -> Result<(), WorkError> {
+        if let Ok(Some(job)) = self.job_persistence.get(job_id).await {
+            if let Ok(JobStatusModel { status: JobStatus::Cancelled, .. }) = JobStatusModel::from_json_slice(&job) {
+                info!("Job '{}' was cancelled", job_id);
+                return Err(WorkError::Cancelled);
+            }
+        }
+        Ok(())
+    }
+
+    /// Source files downloaded by the worker count against the daily quota of the tenant, uploads were counted on creation.
+    pub async fn count_source_bytes<InputType, ResultType>(&self, job: &JobModel<InputType, ResultType>, source_bytes: u64) {
+        if let (Some(tenant), true) = (&job.tenant, source_bytes > 0) {
+            if let Err(err) = self.quota_service.add_source_bytes(tenant, source_bytes).await {
+                error!("Could not count source bytes of job '{}': {}", &job.id, err);
+            }
         }
-        self.callback(job, client).await
     }
 
-    pub async fn error<'a, InputType, ResultType>(&'a self, job: &mut JobModel<InputType, ResultType>, client: &reqwest::Client, err: &str)
-        where JobModel<InputType, ResultType>: GetSelfRoute, ResultType: Clone, JobModel<InputType, ResultType>: Serialize, ResultType: Serialize + Send + Sync, InputType: Serialize + Send + Sync
+    pub async fn retry<InputType, ResultType>(&self, job: &mut JobModel<InputType, ResultType>, err: Error) -> Result<(), WorkError>
+        where ResultType: Serialize + Send + Sync, InputType: Serialize + Send + Sync
+    {
+        info!("Retrying job '{}' after transient error: {}", &job.id, &err);
+        self.transition(job, |job| {
+            job.status = JobStatus::Pending;
+            job.progress = None;
+            job.message = Some(err.to_string());
+            job.error = Some(err.clone());
+        }).await?;
+        Err(WorkError::Retry)
+    }
+
+    pub async fn ready<InputType, ResultType>(&self, job: &mut JobModel<InputType, ResultType>, result: StoredResult<ResultType>) -> Result<(), WorkError>
+        where ResultType: Serialize + Send + Sync, InputType: Serialize + Send + Sync
     {
-        job.message = Some(err.to_string());
-        _ = self.job_persistence.put(job).await;
-        self.callback(job, client).await
+        let finished = Utc::now();
+        job.result = Some(result.result);
+        job.add_files(result.files);
+        self.transition(job, |job| {
+            job.status = JobStatus::Finished;
+            job.finished = Some(finished);
+            job.message = None;
+            job.error = None;
+            if job.callback_uri.is_some() {
+                job.callback = Some(CallbackModel::default());
+            }
+        }).await?;
+        self.observe_duration(job, "finished");
+        self.callback(job).await;
+        Ok(())
+    }
+
+    pub async fn error<InputType, ResultType>(&self, job: &mut JobModel<InputType, ResultType>, err: Error) -> Result<(), WorkError>
+        where ResultType: Serialize + Send + Sync, InputType: Serialize + Send + Sync
+    {
+        let finished = Utc::now();
+        self.transition(job, |job| {
+            job.status = JobStatus::Error;
+            job.finished = Some(finished);
+            job.result = None;
+            job.message = Some(err.to_string());
+            job.error = Some(err.clone());
+            if job.callback_uri.is_some() {
+                job.callback = Some(CallbackModel::default());
+            }
+        }).await?;
+        self.observe_duration(job, "error");
+        self.callback(job).await;
+        Ok(())
     }
 
-    async fn callback<'a, InputType, ResultType>(&'a self, job: &JobModel<InputType, ResultType>, client: &reqwest::Client)
-        where JobModel<InputType, ResultType>: GetSelfRoute, ResultType: Clone, JobModel<InputType, ResultType>: Serialize, ResultType: Serialize, InputType: Serialize
+    /// Writes the job as `change` leaves it unless it was cancelled, a concurrent write makes it re-read and re-check instead of overwriting it.
+    async fn transition<InputType, ResultType, Change>(&self, job: &mut JobModel<InputType, ResultType>, change: Change) -> Result<(), WorkError>
+        where ResultType: Serialize + Send + Sync, InputType: Serialize + Send + Sync, Change: Fn(&mut JobModel<InputType, ResultType>) + Send
     {
-        if let Some(callback_uri) = &job.callback_uri {
-            let dto = job.to_dto();
-            let mut retries = 0;
-            loop {
-                let result = client.post(callback_uri).json(&dto).send().await;
-                match result {
-                    Ok(ok) => {
-                        info!("Send callback '{}' to '{}', with {}", &job.id, callback_uri, ok.status());
-                        break;
-                    },
+        loop {
+            let entry = self.job_persistence.get_entry(&job.id).await.map_err(|_| WorkError::Retry)?.ok_or(WorkError::NoRetry)?;
+            if let Ok(JobStatusModel { status: JobStatus::Cancelled, finished, files }) = JobStatusModel::from_json_slice(&entry.value) {
+                info!("Job '{}' was cancelled", &job.id);
+                // results stored before the cancellation are recorded on the job, so a purge still erases them
+                if job.files.iter().all(|file| files.contains(file)) {
+                    return Err(WorkError::Cancelled);
+                }
+                job.status = JobStatus::Cancelled;
+                job.finished = finished;
+                match self.job_persistence.update(job, entry.revision).await {
+                    Ok(None) => continue,
+                    Ok(Some(_)) => return Err(WorkError::Cancelled),
                     Err(err) => {
-                        retries += 1;
-                        info!("Error sending {} time callback '{}' to '{}', because of {}", retries, &job.id, callback_uri, err);
-                        if retries >= 5 {
-                            break;
-                        }
-                    },
+                        error!("Could not record files of cancelled job '{}': {}", &job.id, err);
+                        return Err(WorkError::Cancelled);
+                    }
+                }
+            }
+            change(job);
+            match self.job_persistence.update(job, entry.revision).await {
+                Ok(Some(_)) => return Ok(()),
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Could not update job '{}': {}", &job.id, err);
+                    return Err(WorkError::Retry);
                 }
             }
         }
     }
-}
\ No newline at end of file
+
+    fn observe_duration<InputType, ResultType>(&self, job: &JobModel<InputType, ResultType>, status: &str) {
+        if let (Some(started), Some(finished)) = (job.started, job.finished) {
+            let duration = finished.signed_duration_since(started).to_std().unwrap_or_default();
+            JOB_DURATION.with_label_values(&[self.job_type, status]).observe(duration.as_secs_f64());
+        }
+    }
+
+    async fn callback<InputType, ResultType>(&self, job: &JobModel<InputType, ResultType>) {
+        if job.callback_uri.is_some() {
+            if let Err(err) = self.callback_publish_service.publish(&job.id).await {
+                error!("Could not publish callback for '{}': {}", &job.id, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Serialize;
+use tokio::{sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::{error::{Error, ErrorCode}, models::{JobModel, JobProgress, JobStatus, JobStatusModel}, nats::subscribe::IHeartbeat, persistence::IJobPersistence};
+
+pub struct ProgressReporter {
+    sender: watch::Sender<JobProgress>,
+    cancelled: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl ProgressReporter {
+    pub fn start<InputType, ResultType>(job: &JobModel<InputType, ResultType>, job_persistence: Arc<dyn IJobPersistence>, heartbeat: Option<Arc<dyn IHeartbeat>>, interval: Duration) -> Self
+        where JobModel<InputType, ResultType>: Clone, InputType: Serialize + Send + Sync + 'static, ResultType: Serialize + Send + Sync + 'static
+    {
+        let (sender, mut receiver) = watch::channel(JobProgress::default());
+        let mut job = job.clone();
+        let cancelled = CancellationToken::new();
+        let cancel = cancelled.clone();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut reporting = true;
+            loop {
+                ticker.tick().await;
+                if let Some(heartbeat) = &heartbeat {
+                    if let Err(err) = heartbeat.heartbeat().await {
+                        warn!("Could not send heartbeat for '{}': {}", &job.id, err);
+                    }
+                }
+                match receiver.has_changed() {
+                    Ok(true) if reporting => {}
+                    Ok(_) => continue,
+                    Err(_) => break,
+                }
+                job.progress = Some(receiver.borrow_and_update().clone());
+                let entry = match job_persistence.get_entry(&job.id).await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        warn!("Could not read job '{}' for progress: {}", &job.id, err);
+                        continue;
+                    }
+                };
+                if is_cancelled(&entry.value) {
+                    cancel.cancel();
+                    reporting = false;
+                    continue;
+                }
+                match job_persistence.update(&job, entry.revision).await {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        // the job was written since it was read, the snapshot is stale and must not overwrite that write
+                        warn!("Stopped persisting progress for '{}' after a concurrent update", &job.id);
+                        reporting = false;
+                        if let Ok(Some(current)) = job_persistence.get(&job.id).await {
+                            if is_cancelled(&current) {
+                                cancel.cancel();
+                            }
+                        }
+                    }
+                    Err(err) => warn!("Could not persist progress for '{}': {}", &job.id, err),
+                }
+            }
+        });
+        ProgressReporter {
+            sender,
+            cancelled,
+            task,
+        }
+    }
+
+    pub fn set_total(&self, total: usize) {
+        self.sender.send_modify(|progress| progress.total = total);
+    }
+
+    pub fn advance(&self) {
+        self.sender.send_modify(|progress| progress.done += 1);
+    }
+
+    /// Fails once a tick saw the job cancelled, so the work stops before storing more results.
+    pub fn ensure_not_cancelled(&self) -> Result<(), Error> {
+        match self.cancelled.is_cancelled() {
+            true => Err(Error::permanent(ErrorCode::Cancelled, "Job was cancelled.")),
+            false => Ok(()),
+        }
+    }
+
+    pub fn get(&self) -> JobProgress {
+        self.sender.borrow().clone()
+    }
+
+    pub async fn stop(mut self) -> JobProgress {
+        self.task.abort();
+        _ = (&mut self.task).await;
+        self.get()
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn is_cancelled(job: &[u8]) -> bool {
+    matches!(JobStatusModel::from_json_slice(job), Ok(JobStatusModel { status: JobStatus::Cancelled, .. }))
+}
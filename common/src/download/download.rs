@@ -2,18 +2,20 @@ use bytes::Bytes;
 use futures::StreamExt;
 use mime::Mime;
 use reqwest::{header::CONTENT_TYPE, Response};
-use std::{path::PathBuf, str::FromStr};
+use std::{path::{Path, PathBuf}, str::FromStr, sync::Arc, time::Instant};
 use tokio::io::AsyncWriteExt;
 
-use crate::{models::SourceFile, persistence::tempfiles::TempJobFileProvider};
+use crate::{error::{Error, ErrorCode}, metrics::{outcome, DOWNLOAD_BYTES, DOWNLOAD_DURATION}, models::SourceFile, persistence::{tempfiles::TempJobFileProvider, IFileStorage, from_internal_uri}};
 
 #[async_trait::async_trait]
 pub trait IDownloadService: Send + Sync {
-    async fn download_source_files(&self, client: &reqwest::Client, source_files: Vec<SourceFile>, job_files: &TempJobFileProvider) -> Vec<Result<DownloadedSourceFile, &'static str>>;
-    async fn download_source(&self, client: &reqwest::Client, source_uri: &str, job_files: &TempJobFileProvider, content_type: &Option<String>) -> Result<(PathBuf, Mime), &'static str>;
-    async fn download_source_bytes(&self, client: &reqwest::Client, source_uri: &str) -> Result<Bytes, &'static str>;
+    /// The results are in the order of `source_files`.
+    async fn download_source_files(&self, client: &reqwest::Client, source_files: Vec<SourceFile>, job_files: &TempJobFileProvider) -> Vec<Result<DownloadedSourceFile, Error>>;
+    async fn download_source(&self, client: &reqwest::Client, source_uri: &str, job_files: &TempJobFileProvider, content_type: &Option<String>) -> Result<(PathBuf, Mime), Error>;
+    async fn download_source_bytes(&self, client: &reqwest::Client, source_uri: &str) -> Result<Bytes, Error>;
 }
 
+#[derive(Clone)]
 pub struct DownloadedSourceFile {
     pub id: String,
     pub path: PathBuf,
@@ -22,48 +24,89 @@ pub struct DownloadedSourceFile {
 
 pub struct DownloadService {
     pub parallelism: usize,
+    pub storage: Arc<dyn IFileStorage>,
 }
 
 #[async_trait::async_trait]
 impl IDownloadService for DownloadService {
-    async fn download_source_files(&self, client: &reqwest::Client, source_files: Vec<SourceFile>, job_files: &TempJobFileProvider) -> Vec<Result<DownloadedSourceFile, &'static str>> {
+    async fn download_source_files(&self, client: &reqwest::Client, source_files: Vec<SourceFile>, job_files: &TempJobFileProvider) -> Vec<Result<DownloadedSourceFile, Error>> {
         let ref_client = &client;
         let ref_job_files = &job_files;
         futures::stream::iter(source_files)
             .map(|source_file| async move { self.download_source_file(ref_client, source_file, ref_job_files).await })
-            .buffer_unordered(self.parallelism)
-            .collect::<Vec<Result<DownloadedSourceFile, &'static str>>>()
+            .buffered(self.parallelism)
+            .collect::<Vec<Result<DownloadedSourceFile, Error>>>()
             .await
     }
 
-    async fn download_source(&self, client: &reqwest::Client, source_uri: &str, job_files: &TempJobFileProvider, content_type: &Option<String>) -> Result<(PathBuf, Mime), &'static str> {
+    async fn download_source(&self, client: &reqwest::Client, source_uri: &str, job_files: &TempJobFileProvider, content_type: &Option<String>) -> Result<(PathBuf, Mime), Error> {
         let path = job_files.get_path();
-        let mut response = client.get(source_uri).send().await.map_err(|_| "Could not load document.")?;
-        let content_type = self.determine_content_type(&response, &content_type)?;
-        let mut file = tokio::fs::File::create(&path).await.map_err(|_| "Could not create file.")?;
-        while let Some(mut item) = response.chunk().await.map_err(|_| "Could not read response.")? {
-            file.write_all_buf(&mut item).await.map_err(|_| "Could not write to file.")?;
+        if let Some(key) = from_internal_uri(source_uri) {
+            let start = Instant::now();
+            let result = self.storage.load_source_file(key, &path).await;
+            DOWNLOAD_DURATION.with_label_values(&["internal", outcome(&result)]).observe(start.elapsed().as_secs_f64());
+            result?;
+            if let Ok(metadata) = tokio::fs::metadata(&path).await {
+                DOWNLOAD_BYTES.with_label_values(&["internal"]).inc_by(metadata.len());
+            }
+            let content_type = match content_type {
+                Some(content_type) => Mime::from_str(content_type).map_err(|err| Error::permanent(ErrorCode::InvalidContentType, "Could not get MimeType.").with_source(err))?,
+                None => mime::APPLICATION_PDF,
+            };
+            return Ok((path, content_type));
         }
-        Ok((path, content_type))
+        let start = Instant::now();
+        let result = self.download_external(client, source_uri, &path, content_type).await;
+        DOWNLOAD_DURATION.with_label_values(&["external", outcome(&result)]).observe(start.elapsed().as_secs_f64());
+        Ok((path, result?))
     }
 
-    async fn download_source_bytes(&self, client: &reqwest::Client, source_uri: &str) -> Result<Bytes, &'static str> {
-        let response = client.get(source_uri).send().await.map_err(|_| "Could not load document.")?;
-        match response.error_for_status() {
-            Ok(response) => response.bytes().await.map_err(|_| "Could not read source."),
-            Err(_) => Err("Could not read source."),
+    async fn download_source_bytes(&self, client: &reqwest::Client, source_uri: &str) -> Result<Bytes, Error> {
+        let start = Instant::now();
+        let result = match self.get(client, source_uri).await {
+            Ok(response) => response.bytes().await.map_err(|err| Error::transient(ErrorCode::Download, "Could not read source.").with_source(err)),
+            Err(err) => Err(err),
+        };
+        DOWNLOAD_DURATION.with_label_values(&["external", outcome(&result)]).observe(start.elapsed().as_secs_f64());
+        if let Ok(bytes) = &result {
+            DOWNLOAD_BYTES.with_label_values(&["external"]).inc_by(bytes.len() as u64);
         }
+        result
     }
 }
 
 impl DownloadService {
-    fn determine_content_type(&self, response: &Response, force_content_type: &Option<String>) -> Result<Mime, &'static str> {
+    async fn download_external(&self, client: &reqwest::Client, source_uri: &str, path: &Path, content_type: &Option<String>) -> Result<Mime, Error> {
+        let mut response = self.get(client, source_uri).await?;
+        let content_type = self.determine_content_type(&response, content_type)?;
+        let mut file = tokio::fs::File::create(path).await.map_err(|err| Error::transient(ErrorCode::Download, "Could not create file.").with_source(err))?;
+        while let Some(mut item) = response.chunk().await.map_err(|err| Error::transient(ErrorCode::Download, "Could not read response.").with_source(err))? {
+            DOWNLOAD_BYTES.with_label_values(&["external"]).inc_by(item.len() as u64);
+            file.write_all_buf(&mut item).await.map_err(|err| Error::transient(ErrorCode::Download, "Could not write to file.").with_source(err))?;
+        }
+        Ok(content_type)
+    }
+
+    async fn get(&self, client: &reqwest::Client, source_uri: &str) -> Result<Response, Error> {
+        let response = client.get(source_uri).send().await.map_err(|err| Error::transient(ErrorCode::Download, "Could not load document.").with_source(err))?;
+        let status = response.status();
+        match status.as_u16() {
+            200..=299 => Ok(response),
+            429 | 500..=599 => Err(Error::transient(ErrorCode::Download, "Could not load document.").with_source(status)),
+            _ => Err(Error::permanent(ErrorCode::Download, "Could not load document.").with_source(status)),
+        }
+    }
+
+    fn determine_content_type(&self, response: &Response, force_content_type: &Option<String>) -> Result<Mime, Error> {
         match force_content_type {
-            Some(content_type) => Ok(Mime::from_str(content_type).map_err(|_| "Could not get MimeType")?),
+            Some(content_type) => Ok(Mime::from_str(content_type).map_err(|err| Error::permanent(ErrorCode::InvalidContentType, "Could not get MimeType.").with_source(err))?),
             None => {
                 let content_type = response.headers().get(CONTENT_TYPE);
                 let content_type = match content_type {
-                    Some(content_type) => Mime::from_str(content_type.to_str().map_err(|_| "Could not get MimeType")?).map_err(|_| "Could not get MimeType")?,
+                    Some(content_type) => {
+                        let content_type = content_type.to_str().map_err(|err| Error::permanent(ErrorCode::InvalidContentType, "Could not get MimeType.").with_source(err))?;
+                        Mime::from_str(content_type).map_err(|err| Error::permanent(ErrorCode::InvalidContentType, "Could not get MimeType.").with_source(err))?
+                    }
                     None => mime::APPLICATION_PDF,
                 };
                 Ok(content_type)
@@ -71,8 +114,11 @@ impl DownloadService {
         }
     }
 
-    async fn download_source_file(&self, client: &reqwest::Client, source_file: SourceFile, job_files: &TempJobFileProvider) -> Result<DownloadedSourceFile, &'static str> {
-        let (path, content_type) = self.download_source(client, &source_file.uri, job_files, &source_file.content_type).await?;
+    async fn download_source_file(&self, client: &reqwest::Client, source_file: SourceFile, job_files: &TempJobFileProvider) -> Result<DownloadedSourceFile, Error> {
+        let (path, content_type) = self.download_source(client, &source_file.uri, job_files, &source_file.content_type).await.map_err(|err| Error {
+            message: format!("{} Source file '{}'.", err.message, source_file.id),
+            ..err
+        })?;
         Ok(DownloadedSourceFile {
             id: source_file.id.clone(),
             path,
@@ -0,0 +1,79 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{models::{DeadLetterModel, QuotaModel}, nats::dlq_subscribe::DLQReason};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterDto {
+    pub sequence: u64,
+    pub stream_sequence: u64,
+    pub reason: DLQReason,
+    pub consumer: Option<String>,
+    pub deliveries: Option<u64>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub job_id: Option<String>,
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterDetailDto {
+    #[serde(flatten)]
+    pub dead_letter: DeadLetterDto,
+    pub payload: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayDeadLettersDto {
+    pub sequences: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayResultDto {
+    pub sequence: u64,
+    pub job_id: Option<String>,
+    pub replayed: bool,
+    pub message: Option<String>,
+}
+
+impl DeadLetterModel {
+    pub fn to_dto(&self) -> DeadLetterDto {
+        DeadLetterDto {
+            sequence: self.sequence,
+            stream_sequence: self.stream_sequence,
+            reason: self.reason,
+            consumer: self.consumer.clone(),
+            deliveries: self.deliveries,
+            timestamp: self.timestamp,
+            job_id: self.job_id.clone(),
+            subject: self.original.as_ref().map(|original| original.subject.clone()),
+        }
+    }
+
+    pub fn to_detail_dto(&self) -> DeadLetterDetailDto {
+        DeadLetterDetailDto {
+            dead_letter: self.to_dto(),
+            payload: self.original.as_ref().map(|original| original.payload.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiKeyDto {
+    pub tenant: String,
+    pub callback_secret: Option<String>,
+    pub quota: Option<QuotaModel>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyDto {
+    pub id: String,
+    pub tenant: String,
+    pub quota: Option<QuotaModel>,
+    pub api_key: Option<String>,
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+use super::ValidationErrorDto;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectResultDto {
+    pub source_files: Vec<InspectSourceFileDto>,
+    pub documents: Vec<InspectDocumentDto>,
+    pub errors: Vec<ValidationErrorDto>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectSourceFileDto {
+    pub id: String,
+    pub content_type: Option<String>,
+    pub page_count: Option<u16>,
+    pub page_sizes: Vec<PageSizeDto>,
+    pub encrypted: Option<bool>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PageSizeDto {
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectDocumentDto {
+    pub id: String,
+    pub page_count: Option<u32>,
+}
@@ -1,19 +1,36 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 
-use crate::models::JobStatus;
+use crate::{error::ErrorCode, models::{CallbackModel, JobStatus, JobProgress}};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JobDto<ResultType> {
     pub id: String,
+    pub tenant: Option<String>,
     pub status: JobStatus,
+    pub started: Option<DateTime<Utc>>,
+    pub finished: Option<DateTime<Utc>>,
+    pub progress: Option<JobProgress>,
     pub message: Option<String>,
+    pub error: Option<JobErrorDto>,
     pub result: Option<ResultType>,
+    pub callback: Option<CallbackModel>,
     #[serde(rename = "_links")]
     pub _links: JobLinks,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobErrorDto {
+    pub code: ErrorCode,
+    pub message: String,
+    pub document_id: Option<String>,
+    pub part_index: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JobLinks {
     #[serde(rename = "self")]
@@ -11,6 +11,18 @@ pub use jobs::*;
 mod root;
 pub use root::*;
 
+mod admin;
+pub use admin::*;
+
+mod validation;
+pub use validation::*;
+
+mod inspect;
+pub use inspect::*;
+
+mod readiness;
+pub use readiness::*;
+
 use crate::models::JobModel;
 
 pub trait GetSelfRoute: Clone {
@@ -21,9 +33,20 @@ impl<InputType, ResultType> JobModel<InputType, ResultType> where JobModel<Input
     pub fn to_dto(&self) -> JobDto<ResultType> {
         JobDto {
             id: self.id.clone(),
+            tenant: self.tenant.clone(),
             status: self.status.clone(),
+            started: self.started,
+            finished: self.finished,
+            progress: self.progress.clone(),
             message: self.message.clone(),
+            error: self.error.as_ref().map(|error| JobErrorDto {
+                code: error.code,
+                message: error.to_string(),
+                document_id: error.document_id.clone(),
+                part_index: error.part_index,
+            }),
             result: self.result.clone(),
+            callback: self.callback.clone(),
             _links: JobLinks {
                 _self: self.get_self_route()
             }
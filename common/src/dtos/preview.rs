@@ -1,14 +1,17 @@
 use crate::models::{PreviewResult, PreviewJobModel};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
 
 use super::{JobDto, GetSelfRoute};
 
 pub type PreviewJobDto = JobDto<PreviewResult>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreatePreviewJobDto {
     pub callback_uri: Option<String>,
+    pub callback_headers: Option<HashMap<String, String>>,
     pub source_uri: String,
     pub source_mime_type: Option<String>,
     pub pdf: Option<bool>,
@@ -17,6 +20,15 @@ pub struct CreatePreviewJobDto {
     pub signatures: Option<bool>,
 }
 
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateSyncPreviewJobDto {
+    pub pdf: Option<bool>,
+    pub png: Option<bool>,
+    pub attachments: Option<bool>,
+    pub signatures: Option<bool>,
+}
+
 impl GetSelfRoute for PreviewJobModel {
     fn get_self_route(&self) -> String {
         format!("/preview/{}?token={}", self.id, self.token)
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RootDto {
     pub version: &'static str,
@@ -9,9 +10,11 @@ pub struct RootDto {
     pub _links: RootLinks,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RootLinks {
     pub transform: &'static str,
     pub preview: &'static str,
+    pub openapi: &'static str,
+    pub docs: &'static str,
 }
@@ -1,19 +1,75 @@
+use std::collections::{HashMap, HashSet};
+
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 
 use crate::models::{TransformResult, Document, SourceFile, TransformJobModel};
 
-use super::{JobDto, GetSelfRoute};
+use super::{JobDto, GetSelfRoute, ValidationErrorDto};
 
 pub type TransformJobDto = JobDto<TransformResult>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateTransformJobDto {
     pub callback_uri: Option<String>,
+    pub callback_headers: Option<HashMap<String, String>>,
     pub documents: Vec<Document>,
     pub source_files: Vec<SourceFile>,
 }
 
+impl CreateTransformJobDto {
+    /// Finds the mistakes a worker would otherwise only report after downloading every source file.
+    pub fn validate(&self) -> Vec<ValidationErrorDto> {
+        let mut errors = Vec::new();
+        let mut source_file_ids = HashSet::new();
+        for (index, source_file) in self.source_files.iter().enumerate() {
+            if source_file.id.is_empty() {
+                errors.push(ValidationErrorDto::new(format!("sourceFiles[{}].id", index), "Source file id must not be empty."));
+            } else if !source_file_ids.insert(source_file.id.as_str()) {
+                errors.push(ValidationErrorDto::new(format!("sourceFiles[{}].id", index), format!("Source file id '{}' is not unique.", source_file.id)));
+            }
+            if source_file.uri.is_empty() {
+                errors.push(ValidationErrorDto::new(format!("sourceFiles[{}].uri", index), format!("Source file '{}' has neither uri nor upload.", source_file.id)));
+            }
+        }
+        if self.documents.is_empty() {
+            errors.push(ValidationErrorDto::new("documents", "At least one document is required."));
+        }
+        let mut document_ids = HashSet::new();
+        for (index, document) in self.documents.iter().enumerate() {
+            let path = format!("documents[{}]", index);
+            if document.id.is_empty() {
+                errors.push(ValidationErrorDto::new(format!("{}.id", path), "Document id must not be empty."));
+            } else if !document_ids.insert(document.id.as_str()) {
+                errors.push(ValidationErrorDto::new(format!("{}.id", path), format!("Document id '{}' is not unique.", document.id)));
+            }
+            if document.parts.is_empty() {
+                errors.push(ValidationErrorDto::new(format!("{}.parts", path), "At least one part is required."));
+            }
+            for (part_index, part) in document.parts.iter().enumerate() {
+                let path = format!("{}.parts[{}]", path, part_index);
+                if !source_file_ids.contains(part.source_file.as_str()) {
+                    errors.push(ValidationErrorDto::new(format!("{}.sourceFile", path), format!("Source file '{}' does not exist.", part.source_file)));
+                }
+                if let Err(err) = part.validate_pages(None) {
+                    errors.push(ValidationErrorDto::new(format!("{}.{}", path, err.field()), err.to_string()));
+                }
+            }
+            for (attachment_index, attachment) in document.attachments.iter().enumerate() {
+                let path = format!("{}.attachments[{}]", path, attachment_index);
+                if !source_file_ids.contains(attachment.source_file.as_str()) {
+                    errors.push(ValidationErrorDto::new(format!("{}.sourceFile", path), format!("Source file '{}' does not exist.", attachment.source_file)));
+                }
+                if attachment.name.is_empty() {
+                    errors.push(ValidationErrorDto::new(format!("{}.name", path), "Attachment name must not be empty."));
+                }
+            }
+        }
+        errors
+    }
+}
+
 impl GetSelfRoute for TransformJobModel {
     fn get_self_route(&self) -> String {
         format!("/transform/{}?token={}", self.id, self.token)
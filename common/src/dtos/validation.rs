@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationErrorsDto {
+    pub errors: Vec<ValidationErrorDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationErrorDto {
+    pub path: String,
+    pub message: String,
+}
+
+impl ValidationErrorDto {
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        ValidationErrorDto {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    Persistence,
+    Storage,
+    Download,
+    InvalidContentType,
+    SourceFileNotFound,
+    InvalidDocument,
+    InvalidPageRange,
+    TransferPages,
+    InvalidImage,
+    Attachment,
+    Render,
+    Save,
+    Serialization,
+    DeadLettered,
+    Cancelled,
+    Executor,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
+    pub source: Option<String>,
+    pub document_id: Option<String>,
+    pub part_index: Option<usize>,
+    pub transient: bool,
+}
+
+impl Error {
+    pub fn permanent(code: ErrorCode, message: impl Into<String>) -> Self {
+        Error {
+            code,
+            message: message.into(),
+            source: None,
+            document_id: None,
+            part_index: None,
+            transient: false,
+        }
+    }
+
+    pub fn transient(code: ErrorCode, message: impl Into<String>) -> Self {
+        Error {
+            transient: true,
+            ..Error::permanent(code, message)
+        }
+    }
+
+    pub fn with_source(mut self, source: impl Display) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    pub fn in_document(mut self, document_id: &str) -> Self {
+        self.document_id.get_or_insert_with(|| document_id.to_string());
+        self
+    }
+
+    pub fn in_part(mut self, part_index: usize) -> Self {
+        self.part_index.get_or_insert(part_index);
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{} ({})", self.message, source),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
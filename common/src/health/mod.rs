@@ -0,0 +1,4 @@
+mod readiness;
+pub use readiness::*;
+mod server;
+pub use server::*;
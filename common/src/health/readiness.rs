@@ -0,0 +1,40 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use crate::dtos::{ReadinessCheckDto, ReadinessDto};
+
+static CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[async_trait::async_trait]
+pub trait IReadinessCheck: Send + Sync {
+    async fn check(&self) -> Result<(), String>;
+}
+
+pub struct ReadinessService {
+    checks: Vec<(&'static str, Arc<dyn IReadinessCheck>)>,
+}
+
+impl ReadinessService {
+    pub fn new(checks: Vec<(&'static str, Arc<dyn IReadinessCheck>)>) -> Self {
+        ReadinessService {
+            checks,
+        }
+    }
+
+    /// Runs all checks concurrently, a check that does not answer in time counts as failed.
+    pub async fn ready(&self) -> ReadinessDto {
+        let results = futures::future::join_all(self.checks.iter().map(|(name, check)| async move {
+            let result = match tokio::time::timeout(CHECK_TIMEOUT, check.check()).await {
+                Ok(result) => result,
+                Err(_) => Err("Check timed out.".to_string()),
+            };
+            (name.to_string(), ReadinessCheckDto {
+                ready: result.is_ok(),
+                error: result.err(),
+            })
+        })).await;
+        ReadinessDto {
+            ready: results.iter().all(|(_, check)| check.ready),
+            checks: results.into_iter().collect::<BTreeMap<_, _>>(),
+        }
+    }
+}
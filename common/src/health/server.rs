@@ -0,0 +1,39 @@
+use std::{convert::Infallible, net::{Ipv6Addr, SocketAddr}, sync::Arc};
+
+use hyper::{header::CONTENT_TYPE, service::{make_service_fn, service_fn}, Body, Method, Request, Response, Server, StatusCode};
+use tracing::info;
+
+use crate::{metrics::{render, METRICS_CONTENT_TYPE}, util::shutdown::Shutdown};
+
+use super::ReadinessService;
+
+/// Serves `/health`, `/ready` and `/metrics` on `port` for workers which have no http server of their own, until `shutdown` is triggered.
+pub async fn serve(port: u16, readiness_service: Arc<ReadinessService>, shutdown: Shutdown) -> Result<(), &'static str> {
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    let make_service = make_service_fn(move |_| {
+        let readiness_service = readiness_service.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(readiness_service.clone(), request))) }
+    });
+    info!("probes listening on {}", addr);
+    Server::try_bind(&addr).map_err(|_| "could not bind probe port")?.serve(make_service).with_graceful_shutdown(async move { shutdown.triggered().await }).await.map_err(|_| "probe server failed")
+}
+
+async fn handle(readiness_service: Arc<ReadinessService>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/health") => Response::builder().status(StatusCode::OK).body(Body::empty()),
+        (&Method::GET, "/ready") => {
+            let readiness = readiness_service.ready().await;
+            let status = match readiness.ready {
+                true => StatusCode::OK,
+                false => StatusCode::SERVICE_UNAVAILABLE,
+            };
+            Response::builder().status(status).header(CONTENT_TYPE, "application/json").body(Body::from(serde_json::to_vec(&readiness).unwrap_or_default()))
+        }
+        (&Method::GET, "/metrics") => match render() {
+            Ok(body) => Response::builder().header(CONTENT_TYPE, METRICS_CONTENT_TYPE).body(Body::from(body)),
+            Err(err) => Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::from(err)),
+        },
+        _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()),
+    };
+    Ok(response.unwrap())
+}
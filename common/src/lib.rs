@@ -5,3 +5,6 @@ pub mod util;
 pub mod nats;
 pub mod download;
 pub mod convert;
+pub mod error;
+pub mod metrics;
+pub mod health;
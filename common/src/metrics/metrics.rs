@@ -0,0 +1,50 @@
+use once_cell::sync::Lazy;
+use prometheus::{exponential_buckets, register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec, TextEncoder};
+
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+pub static JOBS_CREATED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("pdf_jobs_created_total", "Number of created jobs.", &["type"]).unwrap()
+});
+
+pub static JOB_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!("pdf_job_duration_seconds", "Duration of jobs from start until they are finished.", &["type", "status"], exponential_buckets(0.1, 2.0, 14).unwrap()).unwrap()
+});
+
+pub static DOWNLOAD_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("pdf_download_bytes_total", "Number of downloaded source bytes.", &["source"]).unwrap()
+});
+
+pub static DOWNLOAD_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!("pdf_download_duration_seconds", "Duration of source downloads.", &["source", "outcome"], exponential_buckets(0.01, 2.0, 14).unwrap()).unwrap()
+});
+
+pub static PDFIUM_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!("pdf_pdfium_duration_seconds", "Duration of pdfium operations.", &["operation"], exponential_buckets(0.005, 2.0, 16).unwrap()).unwrap()
+});
+
+pub static STORAGE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!("pdf_storage_duration_seconds", "Duration of S3 operations.", &["operation", "outcome"], exponential_buckets(0.005, 2.0, 14).unwrap()).unwrap()
+});
+
+pub static CALLBACKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("pdf_callbacks_total", "Number of callback attempts by outcome.", &["outcome"]).unwrap()
+});
+
+pub static MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("pdf_messages_total", "Number of processed messages by consumer and acknowledgement.", &["consumer", "outcome"]).unwrap()
+});
+
+pub fn outcome<T, E>(result: &Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(_) => "error",
+    }
+}
+
+/// Renders all registered metrics in the prometheus text format.
+pub fn render() -> Result<Vec<u8>, &'static str> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer).map_err(|_| "could not encode metrics")?;
+    Ok(buffer)
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyModel {
+    pub tenant: String,
+    pub callback_secret: Option<String>,
+    #[serde(default)]
+    pub quota: Option<QuotaModel>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaModel {
+    pub jobs_per_minute: Option<u64>,
+    pub max_in_flight: Option<usize>,
+    pub source_bytes_per_day: Option<u64>,
+}
+
+impl QuotaModel {
+    /// Fills the limits not set on this quota from the given defaults.
+    pub fn or(self, defaults: &QuotaModel) -> QuotaModel {
+        QuotaModel {
+            jobs_per_minute: self.jobs_per_minute.or(defaults.jobs_per_minute),
+            max_in_flight: self.max_in_flight.or(defaults.max_in_flight),
+            source_bytes_per_day: self.source_bytes_per_day.or(defaults.source_bytes_per_day),
+        }
+    }
+}
+
+impl ApiKeyModel {
+    /// Api keys are stored under the hex encoded SHA-256 of the key, never the key itself.
+    pub fn get_id(api_key: &str) -> String {
+        hex::encode(Sha256::digest(api_key.as_bytes()))
+    }
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IdempotencyModel {
+    pub request_hash: String,
+    pub job_id: String,
+}
+
+impl IdempotencyModel {
+    /// Keys are scoped by tenant, so two tenants can't collide on the same key.
+    pub fn get_id(tenant: Option<&str>, idempotency_key: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(tenant.unwrap_or_default().as_bytes());
+        hasher.update(b"\n");
+        hasher.update(idempotency_key.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
@@ -1,17 +1,21 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use schemars::{JsonSchema, JsonSchema_repr};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use chrono::serde::ts_seconds;
+use chrono::serde::{ts_seconds, ts_seconds_option};
+
+use crate::error::Error;
 
 use super::ToIdJson;
 
-#[derive(Debug, Serialize_repr, Deserialize_repr, Clone)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, JsonSchema_repr)]
 #[repr(u8)]
 pub enum JobStatus {
     Pending = 0,
     InProgress = 1,
     Finished = 2,
     Error = 3,
+    Cancelled = 4,
 }
 
 pub type BaseJobModel = JobModel<(), ()>;
@@ -21,13 +25,103 @@ pub type BaseJobModel = JobModel<(), ()>;
 pub struct JobModel<InputType, ResultType> {
     pub id: String,
     pub token: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub api_key_id: Option<String>,
     #[serde(with = "ts_seconds")]
     pub created: DateTime<Utc>,
+    #[serde(default, with = "ts_seconds_option")]
+    pub started: Option<DateTime<Utc>>,
+    #[serde(default, with = "ts_seconds_option")]
+    pub finished: Option<DateTime<Utc>>,
     pub status: JobStatus,
     pub message: Option<String>,
+    #[serde(default)]
+    pub error: Option<Error>,
     pub callback_uri: Option<String>,
+    #[serde(default)]
+    pub callback_headers: Option<String>,
     pub input: InputType,
     pub result: Option<ResultType>,
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub progress: Option<JobProgress>,
+    #[serde(default)]
+    pub callback: Option<CallbackModel>,
+}
+
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, PartialEq, JsonSchema_repr)]
+#[repr(u8)]
+pub enum CallbackStatus {
+    Pending = 0,
+    Delivered = 1,
+    Failed = 2,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CallbackModel {
+    pub status: CallbackStatus,
+    pub attempts: u32,
+    #[serde(default, with = "ts_seconds_option")]
+    #[schemars(with = "Option<i64>")]
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+}
+
+impl Default for CallbackModel {
+    fn default() -> Self {
+        CallbackModel {
+            status: CallbackStatus::Pending,
+            attempts: 0,
+            last_attempt: None,
+            last_status_code: None,
+            last_error: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredResult<ResultType> {
+    pub result: ResultType,
+    pub files: Vec<String>,
+}
+
+/// Fails a job after some of its results were stored already, their keys still belong on the job so they can be purged.
+#[derive(Debug, Clone)]
+pub struct StoredError {
+    pub error: Error,
+    pub files: Vec<String>,
+}
+
+impl From<Error> for StoredError {
+    fn from(error: Error) -> Self {
+        StoredError {
+            error,
+            files: Vec::new(),
+        }
+    }
+}
+
+impl<InputType, ResultType> JobModel<InputType, ResultType> {
+    /// Keys are stable across deliveries, so a retried job stores over the same objects.
+    pub fn add_files(&mut self, files: Vec<String>) {
+        for file in files {
+            if !self.files.contains(&file) {
+                self.files.push(file);
+            }
+        }
+    }
 }
 
 impl<InputType, ResultType> ToIdJson for JobModel<InputType, ResultType> where InputType: Serialize + Send + Sync, ResultType: Serialize + Send + Sync {
@@ -39,6 +133,22 @@ impl<InputType, ResultType> ToIdJson for JobModel<InputType, ResultType> where I
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatusModel {
+    pub status: JobStatus,
+    #[serde(default, with = "ts_seconds_option")]
+    pub finished: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+impl JobStatusModel {
+    pub fn from_json_slice(slice: &[u8]) -> Result<Self, &'static str> {
+       serde_json::from_slice(slice).map_err(|_| "job is not valid json")
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct JobLinks {
@@ -10,6 +10,12 @@ pub use jobs::*;
 mod nats;
 pub use nats::*;
 
+mod api_key;
+pub use api_key::*;
+
+mod idempotency;
+pub use idempotency::*;
+
 pub trait ToIdJson: Send + Sync {
     fn to_json(&self) -> Result<String, &'static str>;
     fn get_id(&self) -> &str;
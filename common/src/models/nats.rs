@@ -1,6 +1,35 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::nats::dlq_subscribe::DLQReason;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageDLQModel {
     pub stream_seq: u64,
+    #[serde(default)]
+    pub consumer: Option<String>,
+    #[serde(default)]
+    pub deliveries: Option<u64>,
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterModel {
+    pub sequence: u64,
+    pub stream_sequence: u64,
+    pub reason: DLQReason,
+    pub consumer: Option<String>,
+    pub deliveries: Option<u64>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub job_id: Option<String>,
+    pub original: Option<OriginalMessageModel>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OriginalMessageModel {
+    pub subject: String,
+    pub payload: String,
 }
@@ -1,12 +1,13 @@
 use crate::util::serialize::base64;
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 
 use super::JobModel;
 
 pub type PreviewJobModel = JobModel<PreviewInput, PreviewResult>;
 
 impl PreviewJobModel {
-    pub fn from_json_slice<'a>(slice: &'a [u8]) -> Result<Self, &'static str> {
+    pub fn from_json_slice(slice: &[u8]) -> Result<Self, &'static str> {
        serde_json::from_slice(slice).map_err(|_| "job is not valid json")
     }
 }
@@ -14,7 +15,7 @@ impl PreviewJobModel {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewInput {
-    pub source_uri: String,
+    pub source_uri: Option<String>,
     pub source_mime_type: Option<String>,
     pub pdf: bool,
     pub png: bool,
@@ -22,7 +23,7 @@ pub struct PreviewInput {
     pub signatures: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewResult {
     pub page_count: usize,
@@ -33,23 +34,24 @@ pub struct PreviewResult {
     pub pdf: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewSignature {
     pub signing_date: Option<String>,
     pub reason: Option<String>,
     #[serde(with = "base64")]
+    #[schemars(with = "String")]
     pub signature: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewPageResult {
     pub download_url: String,
     pub text: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewAttachmentResult {
     pub name: String,
@@ -1,4 +1,7 @@
+use std::fmt::Display;
+
 use serde::{Deserialize, Serialize};
+use schemars::{JsonSchema, JsonSchema_repr};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use super::JobModel;
@@ -7,7 +10,7 @@ pub type TransformResult = Vec<TransformDocumentResult>;
 pub type TransformJobModel = JobModel<TransformInput, TransformResult>;
 
 impl TransformJobModel {
-    pub fn from_json_slice<'a>(slice: &'a [u8]) -> Result<Self, &'static str> {
+    pub fn from_json_slice(slice: &[u8]) -> Result<Self, &'static str> {
        serde_json::from_slice(slice).map_err(|_| "job is not valid json")
     }
 }
@@ -19,15 +22,25 @@ pub struct TransformInput {
     pub documents: Vec<Document>,
 }
 
+/// Input of an inspection, the tenant is charged for the source bytes the worker downloads.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
+pub struct InspectInput {
+    pub tenant: Option<String>,
+    #[serde(flatten)]
+    pub input: TransformInput,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct SourceFile {
     pub id: String,
+    #[serde(default)]
     pub uri: String,
     pub content_type: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Part {
     pub source_file: String,
@@ -36,14 +49,61 @@ pub struct Part {
     pub rotation: Option<Rotation>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug)]
+pub enum PageRangeError {
+    StartTooSmall,
+    StartAfterEnd,
+    EndExceedsPages { end_page_number: u16, pages: u16 },
+}
+
+impl PageRangeError {
+    pub fn field(&self) -> &'static str {
+        match self {
+            PageRangeError::StartTooSmall | PageRangeError::StartAfterEnd => "startPageNumber",
+            PageRangeError::EndExceedsPages { .. } => "endPageNumber",
+        }
+    }
+}
+
+impl Display for PageRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PageRangeError::StartTooSmall => write!(f, "Start page number must be at least 1."),
+            PageRangeError::StartAfterEnd => write!(f, "Start page number can't be greater than end page number."),
+            PageRangeError::EndExceedsPages { end_page_number, pages } => write!(f, "End page number {} exceeds {} pages of document.", end_page_number, pages),
+        }
+    }
+}
+
+impl Part {
+    /// Checks the page range of the part, the page count is only known once the source file is loaded.
+    pub fn validate_pages(&self, pages: Option<u16>) -> Result<(), PageRangeError> {
+        let start_page_number = self.start_page_number.unwrap_or(1);
+        if start_page_number == 0 {
+            return Err(PageRangeError::StartTooSmall);
+        }
+        let end_page_number = match self.end_page_number.or(pages) {
+            Some(end_page_number) => end_page_number,
+            None => return Ok(()),
+        };
+        if start_page_number > end_page_number {
+            return Err(PageRangeError::StartAfterEnd);
+        }
+        match pages {
+            Some(pages) if end_page_number > pages => Err(PageRangeError::EndExceedsPages { end_page_number, pages }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Attachment {
     pub source_file: String,
     pub name: String,
 }
 
-#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr)]
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, JsonSchema_repr)]
 #[repr(i32)]
 pub enum Rotation {
     N270 = -270,
@@ -61,7 +121,7 @@ impl Rotation {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Document {
     pub id: String,
@@ -69,9 +129,55 @@ pub struct Document {
     pub attachments: Vec<Attachment>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TransformDocumentResult {
     pub id: String,
     pub download_url: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(start_page_number: Option<u16>, end_page_number: Option<u16>) -> Part {
+        Part {
+            source_file: "source".to_string(),
+            start_page_number,
+            end_page_number,
+            rotation: None,
+        }
+    }
+
+    #[test]
+    fn accepts_open_range_without_page_count() {
+        assert!(part(None, None).validate_pages(None).is_ok());
+        assert!(part(Some(3), None).validate_pages(None).is_ok());
+    }
+
+    #[test]
+    fn accepts_range_within_pages() {
+        assert!(part(Some(1), Some(5)).validate_pages(Some(5)).is_ok());
+        assert!(part(Some(5), None).validate_pages(Some(5)).is_ok());
+    }
+
+    #[test]
+    fn rejects_start_page_zero() {
+        let err = part(Some(0), Some(2)).validate_pages(None).unwrap_err();
+        assert!(matches!(err, PageRangeError::StartTooSmall));
+        assert_eq!(err.field(), "startPageNumber");
+    }
+
+    #[test]
+    fn rejects_start_after_end() {
+        assert!(matches!(part(Some(3), Some(2)).validate_pages(None), Err(PageRangeError::StartAfterEnd)));
+        assert!(matches!(part(Some(6), None).validate_pages(Some(5)), Err(PageRangeError::StartAfterEnd)));
+    }
+
+    #[test]
+    fn rejects_end_past_page_count() {
+        let err = part(Some(1), Some(6)).validate_pages(Some(5)).unwrap_err();
+        assert!(matches!(err, PageRangeError::EndExceedsPages { end_page_number: 6, pages: 5 }));
+        assert_eq!(err.field(), "endPageNumber");
+    }
+}
@@ -1,15 +1,48 @@
-use async_nats::{connect, jetstream::Context};
+use async_nats::{connect, connection::State, jetstream::{response::Response, stream::RawMessage, Context, ErrorCode}, Client};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::health::IReadinessCheck;
 
 pub struct BaseJetStream  {
+    pub client: Client,
     pub jetstream: Context,
 }
 
 impl BaseJetStream {
     pub async fn build(uri: &str) -> Result<Self, &'static str> {
         let nc = connect(uri).await.map_err(|_| "could not connect to nats")?;
-        let jetstream = async_nats::jetstream::new(nc);
+        let jetstream = async_nats::jetstream::new(nc.clone());
         Ok(BaseJetStream {
+            client: nc,
             jetstream,
         })
     }
-}
\ No newline at end of file
+
+    /// Gets a stored message by its sequence, `None` if the stream has no message with it.
+    pub async fn raw_message(&self, stream: &str, sequence: u64) -> Result<Option<RawMessage>, &'static str> {
+        let response: Response<RawMessageResponse> = self.jetstream.request(format!("STREAM.MSG.GET.{}", stream), &json!({ "seq": sequence })).await.map_err(|_| "could not get message")?;
+        match response {
+            Response::Ok(response) => Ok(Some(response.message)),
+            Response::Err { error } if error.error_code() == ErrorCode::NO_MESSAGE_FOUND => Ok(None),
+            Response::Err { .. } => Err("could not get message"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawMessageResponse {
+    message: RawMessage,
+}
+
+#[async_trait::async_trait]
+impl IReadinessCheck for BaseJetStream {
+    async fn check(&self) -> Result<(), String> {
+        let state = self.client.connection_state();
+        if state != State::Connected {
+            return Err(format!("Connection is {}.", state));
+        }
+        self.jetstream.query_account().await.map_err(|err| format!("Could not query JetStream account ({}).", err))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use async_nats::jetstream::stream::Stream;
+
+use crate::{models::{DeadLetterModel, IdModel, MessageDLQModel, OriginalMessageModel}, util::serialize::base64};
+
+use super::{base::BaseJetStream, dlq_subscribe::DLQReason};
+
+#[async_trait::async_trait]
+pub trait IDLQAdminService: Sync + Send {
+    async fn list(&self, limit: usize) -> Result<Vec<DeadLetterModel>, &'static str>;
+    async fn get(&self, sequence: u64) -> Result<Option<DeadLetterModel>, &'static str>;
+    async fn delete(&self, sequence: u64) -> Result<(), &'static str>;
+}
+
+pub struct DLQAdminService {
+    base: Arc<BaseJetStream>,
+    stream: String,
+}
+
+impl DLQAdminService {
+    pub fn new(base: Arc<BaseJetStream>, stream: String) -> Self {
+        DLQAdminService {
+            base,
+            stream,
+        }
+    }
+
+    async fn dlq_stream(&self) -> Result<Stream, &'static str> {
+        self.base.jetstream.get_stream(format!("{}-dlq", self.stream)).await.map_err(|_| "could not get dlq stream")
+    }
+
+    async fn dead_letter(&self, sequence: u64) -> Result<Option<DeadLetterModel>, &'static str> {
+        let message = match self.base.raw_message(&format!("{}-dlq", self.stream), sequence).await? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        let dlq_model: MessageDLQModel = serde_json::from_slice(&base64::deserialize_str(message.payload)?).map_err(|_| "not valid json from dlq")?;
+        let original = self.original(dlq_model.stream_seq).await;
+        let job_id = original.as_ref().and_then(|original| serde_json::from_str::<IdModel>(&original.payload).ok()).map(|content| content.id);
+        Ok(Some(DeadLetterModel {
+            sequence,
+            stream_sequence: dlq_model.stream_seq,
+            reason: DLQReason::from_subject(&message.subject),
+            consumer: dlq_model.consumer,
+            deliveries: dlq_model.deliveries,
+            timestamp: dlq_model.timestamp,
+            job_id,
+            original,
+        }))
+    }
+
+    async fn original(&self, stream_sequence: u64) -> Option<OriginalMessageModel> {
+        let message = self.base.raw_message(&format!("{}-mirror", self.stream), stream_sequence).await.ok()??;
+        let payload = String::from_utf8(base64::deserialize_str(message.payload).ok()?).ok()?;
+        Some(OriginalMessageModel {
+            subject: message.subject,
+            payload,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl IDLQAdminService for DLQAdminService {
+    async fn list(&self, limit: usize) -> Result<Vec<DeadLetterModel>, &'static str> {
+        let mut dlq_stream = self.dlq_stream().await?;
+        let state = dlq_stream.info().await.map_err(|_| "could not get dlq stream info")?.state;
+        let mut dead_letters = Vec::new();
+        if state.messages == 0 {
+            return Ok(dead_letters);
+        }
+        for sequence in (state.first_sequence..=state.last_sequence).rev() {
+            if dead_letters.len() >= limit {
+                break;
+            }
+            if let Some(dead_letter) = self.dead_letter(sequence).await? {
+                dead_letters.push(dead_letter);
+            }
+        }
+        Ok(dead_letters)
+    }
+
+    async fn get(&self, sequence: u64) -> Result<Option<DeadLetterModel>, &'static str> {
+        self.dead_letter(sequence).await
+    }
+
+    async fn delete(&self, sequence: u64) -> Result<(), &'static str> {
+        let dlq_stream = self.dlq_stream().await?;
+        dlq_stream.delete_message(sequence).await.map_err(|_| "could not delete dlq message")?;
+        Ok(())
+    }
+}
+
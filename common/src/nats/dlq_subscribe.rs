@@ -1,34 +1,67 @@
-use std::{sync::Arc, time::Duration};
+use std::{fmt::{Display, Formatter}, sync::Arc, time::Duration};
 
-use async_nats::jetstream::{stream::{Stream, Source}, AckKind};
+use async_nats::jetstream::{stream::{RetentionPolicy, Stream, Source}, AckKind, Message};
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 
-use crate::{models::{IdModel, MessageDLQModel}, util::serialize::base64};
+use crate::{models::{IdModel, MessageDLQModel}, util::{serialize::base64, shutdown::Shutdown}};
 
 use super::base::BaseJetStream;
 
 #[async_trait::async_trait]
 pub trait IDLQSubscribeService: Sync + Send {
-    async fn subscribe(&self) -> Result<(), &'static str>;
+    async fn subscribe(&self, shutdown: Shutdown) -> Result<(), &'static str>;
 }
 
 #[async_trait::async_trait]
 pub trait IDLQWorkerService: Sync + Send {
-    async fn work(&self, id: &str) -> ();
+    async fn work(&self, id: &str, reason: DLQReason) -> Result<(), &'static str>;
+}
+
+static MAX_DELIVERIES_ADVISORY: &str = "$JS.EVENT.ADVISORY.CONSUMER.MAX_DELIVERIES";
+static MSG_TERMINATED_ADVISORY: &str = "$JS.EVENT.ADVISORY.CONSUMER.MSG_TERMINATED";
+static DLQ_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum DLQReason {
+    MaxDeliveries,
+    Terminated,
+}
+
+impl DLQReason {
+    pub fn from_subject(subject: &str) -> Self {
+        match subject.starts_with(MAX_DELIVERIES_ADVISORY) {
+            true => DLQReason::MaxDeliveries,
+            false => DLQReason::Terminated,
+        }
+    }
+}
+
+impl Display for DLQReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DLQReason::MaxDeliveries => write!(f, "Max deliveries exceeded."),
+            DLQReason::Terminated => write!(f, "Message was terminated."),
+        }
+    }
 }
 
 pub struct DLQSubscribeService<DLQWorker>  {
+    base: Arc<BaseJetStream>,
     dlq_stream: Stream,
-    mirror_stream: Stream,
+    mirror_stream: String,
     worker: DLQWorker,
     consumer: String,
+    filter: Vec<String>,
 }
 
 impl<Worker> DLQSubscribeService<Worker> {
     pub async fn build(base: Arc<BaseJetStream>, stream: String, worker: Worker, consumer: String, max_age_mirror: Duration) -> Result<Self, &'static str> {
-        let mirror_stream = base.jetstream.get_or_create_stream(async_nats::jetstream::stream::Config {
-            name: format!("{}-mirror", stream),
+        let mirror_stream = format!("{}-mirror", stream);
+        base.jetstream.get_or_create_stream(async_nats::jetstream::stream::Config {
+            name: mirror_stream.clone(),
             max_messages: 10_000,
             mirror: Some(Source {
                 name: stream.clone(),
@@ -38,52 +71,143 @@ impl<Worker> DLQSubscribeService<Worker> {
             ..Default::default()
         }).await.map_err(|_| "could not get or create stream")?;
 
+        let dlq_name = format!("{}-dlq", stream);
+        // retention can't be changed on an existing stream, a work queue dlq drops the dead letters on ack
+        if let Ok(existing) = base.jetstream.get_stream(&dlq_name).await {
+            let info = existing.cached_info();
+            if info.config.retention == RetentionPolicy::WorkQueue {
+                if info.state.messages > 0 {
+                    error!("Stream '{}' still uses work queue retention and holds {} messages, drain and delete it to migrate", &dlq_name, info.state.messages);
+                    return Err("dlq stream uses work queue retention");
+                }
+                info!("Recreating empty stream '{}' with limits retention", &dlq_name);
+                base.jetstream.delete_stream(&dlq_name).await.map_err(|_| "could not delete work queue dlq stream")?;
+            }
+        }
         let dlq_stream = base.jetstream.get_or_create_stream(async_nats::jetstream::stream::Config {
-            name: format!("{}-dlq", stream),
+            name: dlq_name,
             subjects: vec![
-                format!("$JS.EVENT.ADVISORY.CONSUMER.MAX_DELIVERIES.{}.{}", stream, consumer),
-                format!("$JS.EVENT.ADVISORY.CONSUMER.MSG_TERMINATED.{}.{}", stream, consumer),
+                format!("{}.{}.*", MAX_DELIVERIES_ADVISORY, stream),
+                format!("{}.{}.*", MSG_TERMINATED_ADVISORY, stream),
             ],
-            retention: async_nats::jetstream::stream::RetentionPolicy::WorkQueue,
             max_messages: 10_000,
+            max_age: max_age_mirror,
             ..Default::default()
         }).await.map_err(|_| "could not get or create stream")?;
+        let filter = vec![
+            format!("{}.{}.{}", MAX_DELIVERIES_ADVISORY, stream, consumer),
+            format!("{}.{}.{}", MSG_TERMINATED_ADVISORY, stream, consumer),
+        ];
         Ok(DLQSubscribeService {
-            dlq_stream: dlq_stream,
-            mirror_stream: mirror_stream,
+            base,
+            dlq_stream,
+            mirror_stream,
             worker,
             consumer,
+            filter,
         })
     }
 }
 
 #[async_trait::async_trait]
 impl<Worker> IDLQSubscribeService for DLQSubscribeService<Worker> where Worker: IDLQWorkerService {
-    async fn subscribe(&self) -> Result<(), &'static str> {
-        let consumer = self.dlq_stream.get_or_create_consumer(&format!("{}-dlq", self.consumer), async_nats::jetstream::consumer::pull::Config {
-            durable_name: Some(self.consumer.to_string()),
+    async fn subscribe(&self, shutdown: Shutdown) -> Result<(), &'static str> {
+        let dlq_consumer = format!("{}-dlq", self.consumer);
+        let consumer = self.dlq_stream.get_or_create_consumer(&dlq_consumer, async_nats::jetstream::consumer::pull::Config {
+            name: Some(dlq_consumer.clone()),
+            durable_name: Some(dlq_consumer.clone()),
+            filter_subjects: self.filter.clone(),
             ..Default::default()
         }).await.map_err(|_| "could not get or create consumer")?;
         let mut messages = consumer.messages().await.map_err(|_| "could not get messages")?;
-        while let Some(Ok(msg)) = messages.next().await {
+        loop {
+            let msg = tokio::select! {
+                _ = shutdown.triggered() => break,
+                msg = messages.next() => match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                },
+            };
             info!("procressing next message");
-            let work: Result<(), &'static str> = {
-                let dlq_model: MessageDLQModel = serde_json::from_slice(&msg.payload).map_err(|_| "not valid json from dlq")?;
-                msg.ack_with(AckKind::Progress).await.map_err(|_| "could not progress")?;
-                let orig_message = self.mirror_stream.get_raw_message(dlq_model.stream_seq).await.map_err(|_| "could not get message")?;
-                let payload = String::from_utf8(base64::deserialize_str(orig_message.payload)?).map_err(|_| "error in base64 json")?;
-                let content: IdModel = serde_json::from_str(&payload).map_err(|_| "not valid json")?;
-                
-                info!("## start: {}", &content.id);
-                self.worker.work(&content.id).await;
-                info!("## end: {}", &content.id);
-                msg.ack().await.map_err(|_| "could not ack")?;
-                Ok(())
+            // a failing advisory must not end the subscription, it is either redelivered or dropped
+            let acked = match self.handle(&msg).await {
+                Ok(()) => msg.ack().await,
+                Err(DLQFailure::Retry(err)) => {
+                    error!("Error occured processing message {err}, retrying");
+                    msg.ack_with(AckKind::Nak(Some(DLQ_RETRY_DELAY))).await
+                }
+                Err(DLQFailure::Drop(err)) => {
+                    error!("Error occured processing message {err}, dropping it");
+                    msg.ack_with(AckKind::Term).await
+                }
             };
-            if let Err(err) = work {
-                error!("Error occured processing message {err}");
+            if acked.is_err() {
+                error!("Could not acknowledge dlq message");
             }
         }
         Ok(())
     }
 }
+
+impl<Worker> DLQSubscribeService<Worker> where Worker: IDLQWorkerService {
+    async fn handle(&self, msg: &Message) -> Result<(), DLQFailure> {
+        let dlq_model = advisory(&msg.payload)?;
+        msg.ack_with(AckKind::Progress).await.map_err(|_| DLQFailure::Retry("could not progress"))?;
+        let original = self.base.raw_message(&self.mirror_stream, dlq_model.stream_seq).await.map_err(DLQFailure::Retry)?;
+        let job_id = original_job_id(original.map(|original| original.payload))?;
+        let reason = DLQReason::from_subject(&msg.subject);
+
+        info!("## start: {} ({})", &job_id, reason);
+        self.worker.work(&job_id, reason).await.map_err(DLQFailure::Retry)?;
+        info!("## end: {}", &job_id);
+        Ok(())
+    }
+}
+
+/// What happens to an advisory that could not be handled.
+#[derive(Debug, PartialEq, Eq)]
+enum DLQFailure {
+    /// The worker or NATS failed, the advisory is redelivered.
+    Retry(&'static str),
+    /// Handling it again can't succeed, e.g. the original message already left the mirror.
+    Drop(&'static str),
+}
+
+fn advisory(payload: &[u8]) -> Result<MessageDLQModel, DLQFailure> {
+    serde_json::from_slice(payload).map_err(|_| DLQFailure::Drop("not valid json from dlq"))
+}
+
+fn original_job_id(payload: Option<String>) -> Result<String, DLQFailure> {
+    let payload = payload.ok_or(DLQFailure::Drop("original message is no longer in the mirror"))?;
+    let payload = base64::deserialize_str(payload).map_err(DLQFailure::Drop)?;
+    let content: IdModel = serde_json::from_slice(&payload).map_err(|_| DLQFailure::Drop("not valid json"))?;
+    Ok(content.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_advisory_without_original_message() {
+        assert_eq!(original_job_id(None), Err(DLQFailure::Drop("original message is no longer in the mirror")));
+    }
+
+    #[test]
+    fn drops_advisory_with_invalid_original_message() {
+        assert_eq!(original_job_id(Some("not base64!".to_string())), Err(DLQFailure::Drop("base64 err")));
+        assert_eq!(original_job_id(Some("bm90IGpzb24=".to_string())), Err(DLQFailure::Drop("not valid json")));
+    }
+
+    #[test]
+    fn reads_job_id_of_original_message() {
+        // {"id":"job-1"}
+        assert_eq!(original_job_id(Some("eyJpZCI6ImpvYi0xIn0=".to_string())), Ok("job-1".to_string()));
+    }
+
+    #[test]
+    fn drops_invalid_advisory() {
+        assert!(matches!(advisory(b"{"), Err(DLQFailure::Drop(_))));
+        assert_eq!(advisory(br#"{"stream_seq":7}"#).map(|advisory| advisory.stream_seq), Ok(7));
+    }
+}
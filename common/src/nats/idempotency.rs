@@ -0,0 +1,59 @@
+use std::{sync::Arc, time::Duration};
+
+use async_nats::jetstream::kv::{Config, Operation, Store};
+
+use crate::{error::{Error, ErrorCode}, models::IdempotencyModel};
+
+use super::base::BaseJetStream;
+
+#[async_trait::async_trait]
+pub trait IIdempotencyService: Send + Sync {
+    /// Claims the key for a new request, or returns the request that claimed it before.
+    async fn claim(&self, id: &str, idempotency: &IdempotencyModel) -> Result<Option<IdempotencyModel>, Error>;
+    async fn release(&self, id: &str) -> Result<(), Error>;
+}
+
+pub struct IdempotencyService {
+    key_value: Store,
+}
+
+impl IdempotencyService {
+    pub async fn build(base: Arc<BaseJetStream>, bucket: String, window: Duration) -> Result<Self, &'static str> {
+        let key_value = base.jetstream.create_key_value(Config {
+            bucket,
+            max_age: window,
+            ..Default::default()
+        }).await.map_err(|_| "could not create idempotency key value store bucket")?;
+        Ok(IdempotencyService {
+            key_value,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl IIdempotencyService for IdempotencyService {
+    async fn claim(&self, id: &str, idempotency: &IdempotencyModel) -> Result<Option<IdempotencyModel>, Error> {
+        let json = serde_json::to_vec(idempotency).map_err(|err| Error::permanent(ErrorCode::Serialization, "idempotency key is not valid json").with_source(err))?;
+        // a released key leaves a purge marker behind, its revision is needed to claim the key again
+        let mut revision = 0;
+        for _ in 0..3 {
+            if self.key_value.update(id, json.clone().into(), revision).await.is_ok() {
+                return Ok(None);
+            }
+            let entry = self.key_value.entry(id).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not get idempotency key.").with_source(err))?;
+            match entry {
+                Some(entry) if matches!(entry.operation, Operation::Put) => {
+                    return Ok(Some(serde_json::from_slice(&entry.value).map_err(|err| Error::permanent(ErrorCode::Serialization, "idempotency key is not valid json").with_source(err))?));
+                }
+                Some(entry) => revision = entry.revision,
+                None => revision = 0,
+            }
+        }
+        Err(Error::transient(ErrorCode::Persistence, "Could not claim idempotency key."))
+    }
+
+    async fn release(&self, id: &str) -> Result<(), Error> {
+        self.key_value.purge(id).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not release idempotency key.").with_source(err))?;
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 use std::{sync::Arc, time::Duration};
-use async_nats::jetstream::kv::{Store, Config};
+use async_nats::jetstream::kv::{Store, Config, Operation};
 use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 
-use crate::{models::ToIdJson, persistence::IJobPersistence};
+use crate::{error::{Error, ErrorCode}, health::IReadinessCheck, models::{ApiKeyModel, ToIdJson}, persistence::{IApiKeyPersistence, IJobPersistence, JobEntry}};
 
 use super::base::BaseJetStream;
 
@@ -23,15 +26,91 @@ impl KeyValueStoreService {
     }
 }
 
+#[async_trait::async_trait]
+impl IReadinessCheck for KeyValueStoreService {
+    async fn check(&self) -> Result<(), String> {
+        self.key_value.status().await.map_err(|err| format!("Could not get key value store status ({}).", err))?;
+        Ok(())
+    }
+}
+
 #[async_trait::async_trait]
 impl IJobPersistence for KeyValueStoreService {
-    async fn put(&self, job: &dyn ToIdJson) -> Result<(), &'static str> {
-        let json = job.to_json()?;
-        self.key_value.put(job.get_id(), json.into()).await.map_err(|_| "could not put job")?;
+    async fn put(&self, job: &dyn ToIdJson) -> Result<(), Error> {
+        let json = job.to_json().map_err(|err| Error::permanent(ErrorCode::Serialization, err))?;
+        self.key_value.put(job.get_id(), json.into()).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not put job.").with_source(err))?;
         Ok(())
     }
-    async fn get(&self, job_id: &str) -> Result<Option<Bytes>, &'static str> {
-        let stream = self.key_value.get(job_id).await.map_err(|_| "could not get job")?;
+    async fn get(&self, job_id: &str) -> Result<Option<Bytes>, Error> {
+        let stream = self.key_value.get(job_id).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not get job.").with_source(err))?;
         Ok(stream)
     }
-}
\ No newline at end of file
+    async fn get_entry(&self, job_id: &str) -> Result<Option<JobEntry>, Error> {
+        let entry = self.key_value.entry(job_id).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not get job.").with_source(err))?;
+        Ok(entry.filter(|entry| matches!(entry.operation, Operation::Put)).map(|entry| JobEntry {
+            value: entry.value,
+            revision: entry.revision,
+        }))
+    }
+    async fn update(&self, job: &dyn ToIdJson, revision: u64) -> Result<Option<u64>, Error> {
+        let json = job.to_json().map_err(|err| Error::permanent(ErrorCode::Serialization, err))?;
+        match self.key_value.update(job.get_id(), json.into(), revision).await {
+            Ok(revision) => Ok(Some(revision)),
+            // the client does not expose a wrong last sequence, so a changed revision tells a conflict from a failure
+            Err(err) => match self.key_value.entry(job.get_id()).await {
+                Ok(Some(entry)) if entry.revision != revision => Ok(None),
+                Ok(None) => Ok(None),
+                _ => Err(Error::transient(ErrorCode::Persistence, "Could not update job.").with_source(err)),
+            },
+        }
+    }
+    async fn delete(&self, job_id: &str) -> Result<(), Error> {
+        self.key_value.purge(job_id).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not delete job.").with_source(err))?;
+        Ok(())
+    }
+    async fn watch(&self, job_id: &str) -> Result<BoxStream<'static, Bytes>, Error> {
+        let key_value = self.key_value.clone();
+        let job_id = job_id.to_string();
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut watch = match key_value.watch_with_history(&job_id).await {
+                Ok(watch) => watch,
+                Err(_) => return,
+            };
+            loop {
+                tokio::select! {
+                    _ = sender.closed() => break,
+                    entry = watch.next() => match entry {
+                        Some(Ok(entry)) if matches!(entry.operation, Operation::Put) => {
+                            if sender.send(entry.value).await.is_err() {
+                                break;
+                            }
+                        }
+                        _ => break,
+                    },
+                }
+            }
+        });
+        Ok(ReceiverStream::new(receiver).boxed())
+    }
+}
+
+#[async_trait::async_trait]
+impl IApiKeyPersistence for KeyValueStoreService {
+    async fn get_api_key(&self, api_key_id: &str) -> Result<Option<ApiKeyModel>, Error> {
+        let api_key = self.key_value.get(api_key_id).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not get api key.").with_source(err))?;
+        match api_key {
+            Some(api_key) => Ok(Some(serde_json::from_slice(&api_key).map_err(|err| Error::permanent(ErrorCode::Serialization, "api key is not valid json").with_source(err))?)),
+            None => Ok(None),
+        }
+    }
+    async fn put_api_key(&self, api_key_id: &str, api_key: &ApiKeyModel) -> Result<(), Error> {
+        let json = serde_json::to_vec(api_key).map_err(|err| Error::permanent(ErrorCode::Serialization, "api key is not valid json").with_source(err))?;
+        self.key_value.put(api_key_id, json.into()).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not put api key.").with_source(err))?;
+        Ok(())
+    }
+    async fn delete_api_key(&self, api_key_id: &str) -> Result<(), Error> {
+        self.key_value.purge(api_key_id).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not delete api key.").with_source(err))?;
+        Ok(())
+    }
+}
@@ -2,4 +2,9 @@ pub mod publish;
 pub mod subscribe;
 pub mod base;
 pub mod kv_store;
-pub mod dlq_subscribe;
\ No newline at end of file
+pub mod quota;
+pub mod idempotency;
+pub mod dlq_subscribe;
+pub mod dlq_admin;
+pub mod request;
+pub mod reply_subscribe;
@@ -7,6 +7,7 @@ use super::base::BaseJetStream;
 #[async_trait::async_trait]
 pub trait IPublishService: Sync + Send {
     async fn publish<'a>(&self, id: &'a str) -> Result<(), &'static str>;
+    fn subject(&self) -> &str;
 }
 
 pub struct PublishService  {
@@ -33,4 +34,8 @@ impl IPublishService for PublishService {
         self.base.jetstream.publish(self.stream.clone(), json.into()).await.map_err(|_| "not published")?;
         Ok(())
     }
+
+    fn subject(&self) -> &str {
+        &self.stream
+    }
 }
\ No newline at end of file
@@ -0,0 +1,242 @@
+use std::{sync::Arc, time::Duration};
+
+use async_nats::jetstream::kv::{Config, Operation, Store};
+use chrono::{Timelike, Utc};
+use serde::Deserialize;
+
+use crate::{error::{Error, ErrorCode}, models::{JobStatus, JobStatusModel, QuotaModel}, persistence::IJobPersistence};
+
+use super::base::BaseJetStream;
+
+static IN_FLIGHT_RETRY_AFTER: Duration = Duration::from_secs(30);
+static MAX_UPDATE_ATTEMPTS: usize = 10;
+
+#[derive(Debug)]
+pub enum QuotaError {
+    Exceeded { message: &'static str, retry_after: Duration },
+    Failed(Error),
+}
+
+impl From<Error> for QuotaError {
+    fn from(err: Error) -> Self {
+        QuotaError::Failed(err)
+    }
+}
+
+#[async_trait::async_trait]
+pub trait IQuotaService: Send + Sync {
+    /// Counts a new job against the quota of the tenant, or rejects it with the time after which to retry.
+    async fn admit(&self, tenant: &str, job_id: &str, source_bytes: u64, quota: &QuotaModel) -> Result<(), QuotaError>;
+    async fn add_source_bytes(&self, tenant: &str, source_bytes: u64) -> Result<(), Error>;
+}
+
+pub struct QuotaService {
+    key_value: Store,
+    job_persistence: Arc<dyn IJobPersistence>,
+}
+
+impl QuotaService {
+    pub async fn build(base: Arc<BaseJetStream>, bucket: String, job_persistence: Arc<dyn IJobPersistence>) -> Result<Self, &'static str> {
+        let key_value = base.jetstream.create_key_value(Config {
+            bucket,
+            // long enough to outlive the daily window
+            max_age: Duration::from_secs(60 * 60 * 25),
+            ..Default::default()
+        }).await.map_err(|_| "could not create quota key value store bucket")?;
+        Ok(QuotaService {
+            key_value,
+            job_persistence,
+        })
+    }
+
+    fn key(kind: &str, tenant: &str, window: i64) -> String {
+        format!("{}.{}.{}", kind, hex::encode(tenant), window)
+    }
+
+    async fn entry<T: for<'de> Deserialize<'de> + Default>(&self, key: &str) -> Result<(T, u64), Error> {
+        let entry = self.key_value.entry(key).await.map_err(|err| Error::transient(ErrorCode::Persistence, "Could not get quota.").with_source(err))?;
+        match entry {
+            Some(entry) if matches!(entry.operation, Operation::Put) => Ok((serde_json::from_slice(&entry.value).map_err(|err| Error::permanent(ErrorCode::Serialization, "quota is not valid json").with_source(err))?, entry.revision)),
+            Some(entry) => Ok((T::default(), entry.revision)),
+            None => Ok((T::default(), 0)),
+        }
+    }
+
+    /// Writes the value only if nobody else did since it was read at `revision`.
+    async fn update(&self, key: &str, value: &impl serde::Serialize, revision: u64) -> Result<bool, Error> {
+        let json = serde_json::to_vec(value).map_err(|err| Error::permanent(ErrorCode::Serialization, "quota is not valid json").with_source(err))?;
+        Ok(self.key_value.update(key, json.into(), revision).await.is_ok())
+    }
+
+    /// Adds `amount` to the counter unless it would go past `limit`, returns whether it was added.
+    async fn increment(&self, key: &str, amount: u64, limit: Option<u64>) -> Result<bool, Error> {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let (count, revision) = self.entry::<u64>(key).await?;
+            if !fits(count, amount, limit) {
+                return Ok(false);
+            }
+            if amount == 0 {
+                return Ok(true);
+            }
+            if self.update(key, &(count + amount), revision).await? {
+                return Ok(true);
+            }
+        }
+        Err(Error::transient(ErrorCode::Persistence, "Could not update quota, too many concurrent updates."))
+    }
+
+    /// Takes back an `increment` of a request that was rejected by a later limit.
+    async fn decrement(&self, key: &str, amount: u64) -> Result<(), Error> {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let (count, revision) = self.entry::<u64>(key).await?;
+            if self.update(key, &count.saturating_sub(amount), revision).await? {
+                return Ok(());
+            }
+        }
+        Err(Error::transient(ErrorCode::Persistence, "Could not update quota, too many concurrent updates."))
+    }
+
+    async fn running(&self, job_ids: Vec<String>) -> Result<Vec<String>, Error> {
+        let mut running = Vec::with_capacity(job_ids.len());
+        for job_id in job_ids {
+            let job = match self.job_persistence.get(&job_id).await? {
+                Some(job) => job,
+                None => continue,
+            };
+            if let Ok(JobStatusModel { status: JobStatus::Pending | JobStatus::InProgress, .. }) = JobStatusModel::from_json_slice(&job) {
+                running.push(job_id);
+            }
+        }
+        Ok(running)
+    }
+
+    /// Jobs leave the in flight list lazily, once the list is full it is pruned of every job that is no longer running.
+    async fn reserve_in_flight(&self, tenant: &str, job_id: &str, limit: usize) -> Result<bool, Error> {
+        let key = format!("in_flight.{}", hex::encode(tenant));
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let (mut job_ids, revision) = self.entry::<Vec<String>>(&key).await?;
+            if job_ids.len() >= limit {
+                job_ids = self.running(job_ids).await?;
+                if job_ids.len() >= limit {
+                    return Ok(false);
+                }
+            }
+            job_ids.push(job_id.to_string());
+            if self.update(&key, &job_ids, revision).await? {
+                return Ok(true);
+            }
+        }
+        Err(Error::transient(ErrorCode::Persistence, "Could not update quota, too many concurrent updates."))
+    }
+
+    async fn release_in_flight(&self, tenant: &str, job_id: &str) -> Result<(), Error> {
+        let key = format!("in_flight.{}", hex::encode(tenant));
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let (mut job_ids, revision) = self.entry::<Vec<String>>(&key).await?;
+            job_ids.retain(|id| id != job_id);
+            if self.update(&key, &job_ids, revision).await? {
+                return Ok(());
+            }
+        }
+        Err(Error::transient(ErrorCode::Persistence, "Could not update quota, too many concurrent updates."))
+    }
+
+    /// Frees the in flight slot and, if it was counted, the per minute slot of a rejected request.
+    async fn rollback(&self, tenant: &str, job_id: &str, quota: &QuotaModel, minute_key: Option<&str>) {
+        if let (Some(minute_key), Some(_)) = (minute_key, quota.jobs_per_minute) {
+            _ = self.decrement(minute_key, 1).await;
+        }
+        if quota.max_in_flight.is_some() {
+            _ = self.release_in_flight(tenant, job_id).await;
+        }
+    }
+}
+
+/// Whether `amount` can be added to `count` without going past `limit`, a request with nothing to count still needs some quota left.
+fn fits(count: u64, amount: u64, limit: Option<u64>) -> bool {
+    match limit {
+        Some(limit) => count.saturating_add(amount.max(1)) <= limit,
+        None => true,
+    }
+}
+
+#[async_trait::async_trait]
+impl IQuotaService for QuotaService {
+    async fn admit(&self, tenant: &str, job_id: &str, source_bytes: u64, quota: &QuotaModel) -> Result<(), QuotaError> {
+        let now = Utc::now();
+        let day_key = Self::key("source_bytes", tenant, now.timestamp() / 86400);
+        // every limit is counted in turn, a later rejection takes back what the earlier ones counted
+        if let Some(limit) = quota.max_in_flight {
+            if !self.reserve_in_flight(tenant, job_id, limit).await? {
+                return Err(QuotaError::Exceeded {
+                    message: "Too many jobs in flight.",
+                    retry_after: IN_FLIGHT_RETRY_AFTER,
+                });
+            }
+        }
+        let minute_key = Self::key("jobs", tenant, now.timestamp() / 60);
+        let counted = match quota.jobs_per_minute {
+            Some(limit) => match self.increment(&minute_key, 1, Some(limit)).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(QuotaError::Exceeded {
+                    message: "Jobs per minute exceeded.",
+                    retry_after: Duration::from_secs((60 - now.second()) as u64),
+                }),
+                Err(err) => Err(err.into()),
+            },
+            None => Ok(()),
+        };
+        if let Err(err) = counted {
+            self.rollback(tenant, job_id, quota, None).await;
+            return Err(err);
+        }
+        let counted = match self.increment(&day_key, source_bytes, quota.source_bytes_per_day).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(QuotaError::Exceeded {
+                message: "Source bytes per day exceeded.",
+                retry_after: Duration::from_secs((86400 - now.num_seconds_from_midnight()) as u64),
+            }),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = counted {
+            self.rollback(tenant, job_id, quota, Some(&minute_key)).await;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    async fn add_source_bytes(&self, tenant: &str, source_bytes: u64) -> Result<(), Error> {
+        let key = Self::key("source_bytes", tenant, Utc::now().timestamp() / 86400);
+        self.increment(&key, source_bytes, None).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_without_limit() {
+        assert!(fits(u64::MAX, 10, None));
+    }
+
+    #[test]
+    fn fits_up_to_limit() {
+        assert!(fits(0, 100, Some(100)));
+        assert!(fits(99, 1, Some(100)));
+        assert!(!fits(100, 1, Some(100)));
+    }
+
+    #[test]
+    fn rejects_amount_past_limit() {
+        assert!(!fits(50, 51, Some(100)));
+        assert!(!fits(0, 101, Some(100)));
+    }
+
+    #[test]
+    fn rejects_empty_amount_once_exhausted() {
+        assert!(fits(99, 0, Some(100)));
+        assert!(!fits(100, 0, Some(100)));
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use async_nats::{Client, HeaderMap, Message, Subscriber};
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+use crate::util::shutdown::Shutdown;
+
+use super::{base::BaseJetStream, request::{JOB_ID_HEADER, ERROR_HEADER}};
+
+#[async_trait::async_trait]
+pub trait IReplySubscribeService: Sync + Send {
+    async fn subscribe(&self, shutdown: Shutdown) -> Result<(), &'static str>;
+}
+
+#[async_trait::async_trait]
+pub trait IReplyWorkerService: Sync + Send {
+    async fn work(&self, id: &str, payload: Bytes) -> Result<Bytes, &'static str>;
+}
+
+pub struct ReplySubscribeService<ReplyWorker>  {
+    base: Arc<BaseJetStream>,
+    subject: String,
+    queue_group: String,
+    worker: Arc<ReplyWorker>,
+    concurrency: usize,
+}
+
+impl<Worker> ReplySubscribeService<Worker> {
+    pub fn new(base: Arc<BaseJetStream>, subject: String, queue_group: String, worker: Worker, concurrency: usize) -> Self {
+        ReplySubscribeService {
+            base,
+            subject,
+            queue_group,
+            worker: Arc::new(worker),
+            concurrency: concurrency.max(1),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Worker> IReplySubscribeService for ReplySubscribeService<Worker> where Worker: IReplyWorkerService + 'static {
+    async fn subscribe(&self, shutdown: Shutdown) -> Result<(), &'static str> {
+        let mut messages: Subscriber = self.base.client.queue_subscribe(self.subject.clone(), self.queue_group.clone()).await.map_err(|_| "could not subscribe")?;
+        let mut in_flight = JoinSet::new();
+        loop {
+            if in_flight.len() >= self.concurrency {
+                tokio::select! {
+                    _ = shutdown.triggered() => break,
+                    _ = in_flight.join_next() => continue,
+                }
+            }
+            let msg = tokio::select! {
+                _ = shutdown.triggered() => break,
+                Some(_) = in_flight.join_next(), if !in_flight.is_empty() => continue,
+                msg = messages.next() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+            };
+            info!("procressing next request");
+            let handler = RequestHandler {
+                client: self.base.client.clone(),
+                worker: self.worker.clone(),
+                shutdown: shutdown.clone(),
+            };
+            in_flight.spawn(async move {
+                if let Err(err) = handler.handle(msg).await {
+                    error!("Error occured processing request {err}");
+                }
+            });
+        }
+        while in_flight.join_next().await.is_some() {}
+        Ok(())
+    }
+}
+
+struct RequestHandler<Worker> {
+    client: Client,
+    worker: Arc<Worker>,
+    shutdown: Shutdown,
+}
+
+impl<Worker> RequestHandler<Worker> where Worker: IReplyWorkerService {
+    async fn handle(&self, msg: Message) -> Result<(), &'static str> {
+        let reply = msg.reply.clone().ok_or("request without reply subject")?;
+        let id = msg.headers.as_ref().and_then(|headers| headers.get(JOB_ID_HEADER)).ok_or("request without job id")?.to_string();
+        info!("## start: {}", &id);
+        let result = tokio::select! {
+            result = self.worker.work(&id, msg.payload.clone()) => result,
+            _ = self.shutdown.expired() => Err("shutting down"),
+        };
+        info!("## end: {} with {:?}", &id, result.as_ref().err());
+        match result {
+            Ok(payload) => self.client.publish(reply, payload).await.map_err(|_| "could not reply")?,
+            Err(err) => {
+                let mut headers = HeaderMap::new();
+                headers.insert(ERROR_HEADER, err);
+                self.client.publish_with_headers(reply, headers, Bytes::new()).await.map_err(|_| "could not reply")?
+            }
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::{sync::Arc, time::Duration};
+
+use async_nats::{client::RequestErrorKind, HeaderMap, Request};
+use bytes::Bytes;
+
+use super::base::BaseJetStream;
+
+pub static JOB_ID_HEADER: &str = "Job-Id";
+pub static ERROR_HEADER: &str = "Error";
+
+#[async_trait::async_trait]
+pub trait IRequestService: Sync + Send {
+    async fn request(&self, id: &str, payload: Bytes) -> Result<Bytes, RequestError>;
+    fn max_payload(&self) -> usize;
+}
+
+#[derive(Debug, Clone)]
+pub enum RequestError {
+    TooLarge,
+    TimedOut,
+    NoResponders,
+    Failed(String),
+}
+
+pub struct RequestService  {
+    base: Arc<BaseJetStream>,
+    subject: String,
+    timeout: Duration,
+    max_payload: usize,
+}
+
+impl RequestService {
+    pub fn new(base: Arc<BaseJetStream>, subject: String, timeout: Duration, max_payload: usize) -> Self {
+        let max_payload = max_payload.min(base.client.server_info().max_payload);
+        RequestService {
+            base,
+            subject,
+            timeout,
+            max_payload,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IRequestService for RequestService {
+    async fn request(&self, id: &str, payload: Bytes) -> Result<Bytes, RequestError> {
+        if payload.len() > self.max_payload {
+            return Err(RequestError::TooLarge);
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert(JOB_ID_HEADER, id);
+        let request = Request::new().headers(headers).payload(payload).timeout(Some(self.timeout));
+        let response = self.base.client.send_request(self.subject.clone(), request).await.map_err(|err| match err.kind() {
+            RequestErrorKind::TimedOut => RequestError::TimedOut,
+            RequestErrorKind::NoResponders => RequestError::NoResponders,
+            RequestErrorKind::Other => RequestError::Failed("not requested".to_string()),
+        })?;
+        if let Some(err) = response.headers.as_ref().and_then(|headers| headers.get(ERROR_HEADER)) {
+            return Err(RequestError::Failed(err.to_string()));
+        }
+        Ok(response.payload)
+    }
+
+    fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+}
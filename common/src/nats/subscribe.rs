@@ -1,48 +1,68 @@
 use std::{sync::Arc, time::Duration};
 
-use async_nats::jetstream::{stream::{Stream, RetentionPolicy}, AckKind};
+use async_nats::jetstream::{stream::{Stream, RetentionPolicy}, AckKind, Message};
 use futures::StreamExt;
+use tokio::task::JoinSet;
 use tracing::{error, info};
 
-use crate::models::IdModel;
+use crate::{error::Error, metrics::MESSAGES, models::IdModel, util::shutdown::Shutdown};
 
 use super::base::BaseJetStream;
 
+static RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+static RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+
 #[async_trait::async_trait]
 pub trait ISubscribeService: Sync + Send {
-    async fn subscribe(&self) -> Result<(), &'static str>;
+    /// Stops pulling messages once `shutdown` is triggered, a job not done within the grace period is nacked for redelivery.
+    async fn subscribe(&self, shutdown: Shutdown) -> Result<(), &'static str>;
 }
 
 #[async_trait::async_trait]
 pub trait IWorkerService: Sync + Send {
-    async fn work(&self, id: &str) -> Result<(), WorkError>;
+    async fn work(&self, id: &str, delivery: Delivery, heartbeat: Arc<dyn IHeartbeat>) -> Result<(), WorkError>;
+}
+
+#[async_trait::async_trait]
+pub trait IHeartbeat: Sync + Send {
+    async fn heartbeat(&self) -> Result<(), &'static str>;
+}
+
+#[async_trait::async_trait]
+impl IHeartbeat for Message {
+    async fn heartbeat(&self) -> Result<(), &'static str> {
+        self.ack_with(AckKind::Progress).await.map_err(|_| "could not progress")
+    }
 }
 
 pub struct SubscribeService<Worker>  {
     stream: Stream,
-    worker: Worker,
+    worker: Arc<Worker>,
     consumer: String,
     filter: Vec<String>,
     max_deliver: i64,
     ack_wait: Duration,
+    concurrency: usize,
 }
 
 impl<Worker> SubscribeService<Worker> {
-    pub async fn build(base: Arc<BaseJetStream>, stream: String, subjects: Vec<String>, worker: Worker, consumer: String, filter: Vec<String>, max_deliver: i64, ack_wait: Duration) -> Result<Self, &'static str> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn build(base: Arc<BaseJetStream>, stream: String, subjects: Vec<String>, worker: Worker, consumer: String, filter: Vec<String>, max_deliver: i64, ack_wait: Duration, concurrency: usize) -> Result<Self, &'static str> {
         let stream = base.jetstream.get_or_create_stream(async_nats::jetstream::stream::Config {
             name: stream,
-            subjects: subjects,
+            subjects,
             max_messages: 10_000,
             retention: RetentionPolicy::Interest,
             ..Default::default()
         }).await.map_err(|_| "could not get or create stream")?;
         Ok(SubscribeService {
             stream,
-            worker,
+            worker: Arc::new(worker),
             consumer,
             filter,
             max_deliver,
             ack_wait,
+            concurrency: concurrency.max(1),
         })
     }
 }
@@ -51,11 +71,29 @@ impl<Worker> SubscribeService<Worker> {
 pub enum WorkError {
     NoRetry,
     Retry,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Delivery {
+    pub attempt: i64,
+    pub last: bool,
+}
+
+impl Delivery {
+    pub fn should_retry(&self, err: &Error) -> bool {
+        err.transient && !self.last
+    }
+
+    fn retry_delay(&self) -> Duration {
+        let exponent = (self.attempt.clamp(1, 16) - 1) as u32;
+        RETRY_BASE_DELAY.saturating_mul(2u32.pow(exponent)).min(RETRY_MAX_DELAY)
+    }
 }
 
 #[async_trait::async_trait]
-impl<Worker> ISubscribeService for SubscribeService<Worker> where Worker: IWorkerService {
-    async fn subscribe(&self) -> Result<(), &'static str> {
+impl<Worker> ISubscribeService for SubscribeService<Worker> where Worker: IWorkerService + 'static {
+    async fn subscribe(&self, shutdown: Shutdown) -> Result<(), &'static str> {
         let consumer = self.stream.get_or_create_consumer(&self.consumer, async_nats::jetstream::consumer::pull::Config {
             name: Some(self.consumer.clone()),
             filter_subjects: self.filter.clone(),
@@ -64,26 +102,116 @@ impl<Worker> ISubscribeService for SubscribeService<Worker> where Worker: IWorke
             ack_wait: self.ack_wait,
             ..Default::default()
         }).await.map_err(|_| "could not get or create consumer")?;
-        let mut messages = consumer.messages().await.map_err(|_| "could not get messages")?;
-        while let Some(Ok(msg)) = messages.next().await {
+        // only pull what can be started right away, so buffered messages do not run into the ack wait
+        let mut messages = consumer.stream().max_messages_per_batch(self.concurrency).messages().await.map_err(|_| "could not get messages")?;
+        let mut in_flight = JoinSet::new();
+        loop {
+            if in_flight.len() >= self.concurrency {
+                tokio::select! {
+                    _ = shutdown.triggered() => break,
+                    _ = in_flight.join_next() => continue,
+                }
+            }
+            let msg = tokio::select! {
+                _ = shutdown.triggered() => break,
+                Some(_) = in_flight.join_next(), if !in_flight.is_empty() => continue,
+                msg = messages.next() => match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                },
+            };
             info!("procressing next message");
-            let work: Result<(), &'static str> = {
-                let content: IdModel = serde_json::from_slice(&msg.payload).map_err(|_| "not valid json")?;
-                msg.ack_with(AckKind::Progress).await.map_err(|_| "could not progress")?;
-                info!("## start: {}", &content.id);
-                let result = self.worker.work(&content.id).await;
-                info!("## end: {} with {:?}", &content.id, &result);
-                match result {
-                    Ok(()) => msg.ack().await.map_err(|_| "could not ack")?,
-                    Err(WorkError::NoRetry) => msg.ack_with(AckKind::Term).await.map_err(|_| "could not term")?,
-                    Err(WorkError::Retry) => msg.ack_with(AckKind::Nak(None)).await.map_err(|_| "could not nak")?,
-                };
-                Ok(())
+            let handler = MessageHandler {
+                worker: self.worker.clone(),
+                consumer: self.consumer.clone(),
+                max_deliver: self.max_deliver,
+                shutdown: shutdown.clone(),
             };
-            if let Err(err) = work {
-                error!("Error occured processing message {err}");
-            }
+            in_flight.spawn(async move {
+                if let Err(err) = handler.handle(msg).await {
+                    error!("Error occured processing message {err}");
+                }
+            });
         }
+        while in_flight.join_next().await.is_some() {}
         Ok(())
     }
 }
+
+struct MessageHandler<Worker> {
+    worker: Arc<Worker>,
+    consumer: String,
+    max_deliver: i64,
+    shutdown: Shutdown,
+}
+
+impl<Worker> MessageHandler<Worker> where Worker: IWorkerService {
+    async fn handle(&self, msg: Message) -> Result<(), &'static str> {
+        let content: IdModel = serde_json::from_slice(&msg.payload).map_err(|_| "not valid json")?;
+        let attempt = msg.info().map_err(|_| "could not get message info")?.delivered;
+        let delivery = Delivery {
+            attempt,
+            last: self.max_deliver > 0 && attempt >= self.max_deliver,
+        };
+        msg.ack_with(AckKind::Progress).await.map_err(|_| "could not progress")?;
+        info!("## start: {} (attempt {})", &content.id, attempt);
+        let result = tokio::select! {
+            result = self.worker.work(&content.id, delivery, Arc::new(msg.clone())) => result,
+            _ = self.shutdown.expired() => {
+                info!("## abandoned: {} after grace period", &content.id);
+                MESSAGES.with_label_values(&[&self.consumer, "nak"]).inc();
+                return msg.ack_with(AckKind::Nak(None)).await.map_err(|_| "could not nak");
+            }
+        };
+        info!("## end: {} with {:?}", &content.id, &result);
+        let outcome = match result {
+            Ok(()) => "ack",
+            Err(WorkError::NoRetry) | Err(WorkError::Cancelled) => "term",
+            Err(WorkError::Retry) => "nak",
+        };
+        MESSAGES.with_label_values(&[&self.consumer, outcome]).inc();
+        match result {
+            Ok(()) => msg.ack().await.map_err(|_| "could not ack"),
+            Err(WorkError::NoRetry) | Err(WorkError::Cancelled) => msg.ack_with(AckKind::Term).await.map_err(|_| "could not term"),
+            Err(WorkError::Retry) => msg.ack_with(AckKind::Nak(Some(delivery.retry_delay()))).await.map_err(|_| "could not nak"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorCode;
+
+    use super::*;
+
+    fn delivery(attempt: i64, last: bool) -> Delivery {
+        Delivery { attempt, last }
+    }
+
+    #[test]
+    fn retries_transient_errors_before_the_last_delivery() {
+        let err = Error::transient(ErrorCode::Download, "timed out");
+        assert!(delivery(1, false).should_retry(&err));
+        assert!(!delivery(5, true).should_retry(&err));
+    }
+
+    #[test]
+    fn never_retries_permanent_errors() {
+        let err = Error::permanent(ErrorCode::InvalidDocument, "broken");
+        assert!(!delivery(1, false).should_retry(&err));
+    }
+
+    #[test]
+    fn doubles_retry_delay_per_attempt() {
+        assert_eq!(delivery(1, false).retry_delay(), Duration::from_secs(5));
+        assert_eq!(delivery(2, false).retry_delay(), Duration::from_secs(10));
+        assert_eq!(delivery(4, false).retry_delay(), Duration::from_secs(40));
+    }
+
+    #[test]
+    fn caps_retry_delay() {
+        assert_eq!(delivery(7, false).retry_delay(), RETRY_MAX_DELAY);
+        assert_eq!(delivery(i64::MAX, false).retry_delay(), RETRY_MAX_DELAY);
+        assert_eq!(delivery(0, false).retry_delay(), RETRY_BASE_DELAY);
+    }
+}
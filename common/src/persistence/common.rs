@@ -1,14 +1,47 @@
+use std::path::Path;
+
 use bytes::Bytes;
+use futures::stream::BoxStream;
+
+use crate::{error::Error, models::{ApiKeyModel, ToIdJson}};
+
+pub static INTERNAL_URI_PREFIX: &str = "storage://";
 
-use crate::models::ToIdJson;
+pub struct JobEntry {
+    pub value: Bytes,
+    pub revision: u64,
+}
 
 #[async_trait::async_trait]
 pub trait IJobPersistence: Send + Sync {
-    async fn get(&self, job_id: &str) -> Result<Option<Bytes>, &'static str>;
-    async fn put(&self, job: &dyn ToIdJson) -> Result<(), &'static str>;
+    async fn get(&self, job_id: &str) -> Result<Option<Bytes>, Error>;
+    async fn put(&self, job: &dyn ToIdJson) -> Result<(), Error>;
+    async fn get_entry(&self, job_id: &str) -> Result<Option<JobEntry>, Error>;
+    /// Puts the job only if it is still at `revision`, `None` means it was written since and has to be re-read.
+    async fn update(&self, job: &dyn ToIdJson, revision: u64) -> Result<Option<u64>, Error>;
+    async fn delete(&self, job_id: &str) -> Result<(), Error>;
+    async fn watch(&self, job_id: &str) -> Result<BoxStream<'static, Bytes>, Error>;
+}
+
+#[async_trait::async_trait]
+pub trait IApiKeyPersistence: Send + Sync {
+    async fn get_api_key(&self, api_key_id: &str) -> Result<Option<ApiKeyModel>, Error>;
+    async fn put_api_key(&self, api_key_id: &str, api_key: &ApiKeyModel) -> Result<(), Error>;
+    async fn delete_api_key(&self, api_key_id: &str) -> Result<(), Error>;
 }
 
 #[async_trait::async_trait]
 pub trait IFileStorage: Send + Sync {
-    async fn store_result_file(&self, key: &str, file_name: &str, mime_type: Option<&str>, source: Vec<u8>) -> Result<String, &'static str>;
-}
\ No newline at end of file
+    async fn store_result_file(&self, key: &str, file_name: &str, mime_type: Option<&str>, source: Vec<u8>) -> Result<String, Error>;
+    async fn store_source_file(&self, key: &str, source: Vec<u8>) -> Result<String, Error>;
+    async fn load_source_file(&self, key: &str, path: &Path) -> Result<(), Error>;
+    async fn delete_file(&self, key: &str) -> Result<(), Error>;
+}
+
+pub fn to_internal_uri(key: &str) -> String {
+    format!("{}{}", INTERNAL_URI_PREFIX, key)
+}
+
+pub fn from_internal_uri(uri: &str) -> Option<&str> {
+    uri.strip_prefix(INTERNAL_URI_PREFIX)
+}
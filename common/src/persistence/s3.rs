@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path, time::Instant};
 
 use s3::{Bucket, creds::Credentials, region::Region};
 
-use crate::util::stream::VecReader;
+use crate::{error::{Error, ErrorCode}, health::IReadinessCheck, metrics::{outcome, STORAGE_DURATION}, util::stream::VecReader};
 
-use super::IFileStorage;
+use super::{IFileStorage, to_internal_uri};
 
 pub struct S3FileStorage {
     bucket: Bucket,
@@ -22,21 +22,68 @@ impl S3FileStorage {
             expire_seconds,
         })
     }
-}
 
-#[async_trait::async_trait]
-impl IFileStorage for S3FileStorage {   
-    async fn store_result_file(&self, key: &str, file_name: &str, mime_type: Option<&str>, source: Vec<u8>) -> Result<String, &'static str> {
+    async fn put(&self, key: &str, source: Vec<u8>) -> Result<(), Error> {
+        let start = Instant::now();
         let mut vec_reader = VecReader {
             vec: source,
         };
-        self.bucket.put_object_stream(&mut vec_reader, key).await.map_err(|_| "could not put blob")?;
+        let result = match self.bucket.put_object_stream(&mut vec_reader, key).await {
+            Ok(status) => check_status(status, "Could not put blob."),
+            Err(err) => Err(Error::transient(ErrorCode::Storage, "Could not put blob.").with_source(err)),
+        };
+        STORAGE_DURATION.with_label_values(&["upload", outcome(&result)]).observe(start.elapsed().as_secs_f64());
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl IFileStorage for S3FileStorage {   
+    async fn store_result_file(&self, key: &str, file_name: &str, _mime_type: Option<&str>, source: Vec<u8>) -> Result<String, Error> {
+        self.put(key, source).await?;
         let mut custom_queries = HashMap::new();
         custom_queries.insert(
             "response-content-disposition".into(),
             format!("attachment; filename=\"{}\"", file_name),
         );
-        let presigned = self.bucket.presign_get(key, self.expire_seconds, Some(custom_queries)).map_err(|_| "could not get presigned url")?;
+        let presigned = self.bucket.presign_get(key, self.expire_seconds, Some(custom_queries)).map_err(|err| Error::permanent(ErrorCode::Storage, "Could not get presigned url.").with_source(err))?;
         Ok(presigned)
     }
+
+    async fn store_source_file(&self, key: &str, source: Vec<u8>) -> Result<String, Error> {
+        self.put(key, source).await?;
+        Ok(to_internal_uri(key))
+    }
+
+    async fn load_source_file(&self, key: &str, path: &Path) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(path).await.map_err(|err| Error::transient(ErrorCode::Storage, "Could not create file.").with_source(err))?;
+        let start = Instant::now();
+        let result = match self.bucket.get_object_to_writer(key, &mut file).await {
+            Ok(status) => check_status(status, "Could not get blob."),
+            Err(err) => Err(Error::transient(ErrorCode::Storage, "Could not get blob.").with_source(err)),
+        };
+        STORAGE_DURATION.with_label_values(&["download", outcome(&result)]).observe(start.elapsed().as_secs_f64());
+        result
+    }
+
+    async fn delete_file(&self, key: &str) -> Result<(), Error> {
+        let response = self.bucket.delete_object(key).await.map_err(|err| Error::transient(ErrorCode::Storage, "Could not delete blob.").with_source(err))?;
+        check_status(response.status_code(), "Could not delete blob.")
+    }
+}
+
+#[async_trait::async_trait]
+impl IReadinessCheck for S3FileStorage {
+    async fn check(&self) -> Result<(), String> {
+        self.bucket.list_page(String::new(), None, None, None, Some(1)).await.map_err(|err| format!("Could not list bucket ({}).", err))?;
+        Ok(())
+    }
+}
+
+fn check_status(status: u16, message: &'static str) -> Result<(), Error> {
+    match status {
+        200..=299 => Ok(()),
+        429 | 500..=599 => Err(Error::transient(ErrorCode::Storage, message).with_source(format!("status {}", status))),
+        _ => Err(Error::permanent(ErrorCode::Storage, message).with_source(format!("status {}", status))),
+    }
 }
@@ -27,6 +27,10 @@ impl TempJobFileProvider {
         }
     }
 
+    pub fn directory(&self) -> PathBuf {
+        self.job_directory.clone()
+    }
+
     pub fn get_path(&self) -> PathBuf {
         self.job_directory.join(generate_30_alphanumeric())
     }
@@ -34,4 +38,15 @@ impl TempJobFileProvider {
     pub fn get_one() -> PathBuf {
         env::temp_dir().join(generate_30_alphanumeric())
     }
-}
\ No newline at end of file
+}
+
+/// Removes what is left when the job future was dropped before `clean_up`, e.g. after the shutdown grace period.
+impl Drop for TempJobFileProvider {
+    fn drop(&mut self) {
+        if self.job_directory.exists() {
+            if let Err(err) = std::fs::remove_dir_all(&self.job_directory) {
+                warn!("Error occured, while deleting temp job files for {}: {}", &self.job_directory.to_str().unwrap_or("<none>"), &err)
+            }
+        }
+    }
+}
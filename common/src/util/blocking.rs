@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+/// Runs CPU heavy work on the blocking threads of tokio, but never more than `size` at once.
+#[derive(Clone)]
+pub struct BlockingPool {
+    permits: Arc<Semaphore>,
+}
+
+impl BlockingPool {
+    pub fn new(size: usize) -> Self {
+        BlockingPool {
+            permits: Arc::new(Semaphore::new(size.max(1))),
+        }
+    }
+
+    pub async fn run<F, R>(&self, work: F) -> Result<R, &'static str>
+        where F: FnOnce() -> R + Send + 'static, R: Send + 'static
+    {
+        let _permit = self.permits.acquire().await.map_err(|_| "blocking pool is closed")?;
+        tokio::task::spawn_blocking(work).await.map_err(|_| "blocking work panicked")
+    }
+}
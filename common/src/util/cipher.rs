@@ -0,0 +1,105 @@
+use base64::{engine::general_purpose, Engine};
+use ring::{aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN}, rand::{SecureRandom, SystemRandom}};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// AES-256-GCM with a random nonce, sealed values are `base64(nonce || ciphertext || tag)`.
+pub struct Cipher {
+    key: LessSafeKey,
+    random: SystemRandom,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8]) -> Result<Self, &'static str> {
+        let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| "key must be 32 bytes")?;
+        Ok(Cipher {
+            key: LessSafeKey::new(key),
+            random: SystemRandom::new(),
+        })
+    }
+
+    pub fn from_base64(key: &str) -> Result<Self, &'static str> {
+        Cipher::new(&general_purpose::STANDARD.decode(key).map_err(|_| "key is not valid base64")?)
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Result<String, &'static str> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.random.fill(&mut nonce).map_err(|_| "could not generate nonce")?;
+        let mut sealed = plaintext.to_vec();
+        self.key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed).map_err(|_| "could not encrypt")?;
+        let mut result = nonce.to_vec();
+        result.extend(sealed);
+        Ok(general_purpose::STANDARD.encode(result))
+    }
+
+    pub fn open(&self, sealed: &str) -> Result<Vec<u8>, &'static str> {
+        let sealed = general_purpose::STANDARD.decode(sealed).map_err(|_| "sealed value is not valid base64")?;
+        if sealed.len() < NONCE_LEN {
+            return Err("sealed value is too short");
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "invalid nonce")?;
+        let mut ciphertext = ciphertext.to_vec();
+        let plaintext = self.key.open_in_place(nonce, Aad::empty(), &mut ciphertext).map_err(|_| "could not decrypt")?;
+        Ok(plaintext.to_vec())
+    }
+
+    pub fn seal_json<T: Serialize>(&self, value: &T) -> Result<String, &'static str> {
+        self.seal(&serde_json::to_vec(value).map_err(|_| "not valid json")?)
+    }
+
+    pub fn open_json<T: DeserializeOwned>(&self, sealed: &str) -> Result<T, &'static str> {
+        serde_json::from_slice(&self.open(sealed)?).map_err(|_| "not valid json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> Cipher {
+        Cipher::new(&[7u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn opens_what_it_sealed() {
+        let cipher = cipher();
+        let sealed = cipher.seal(b"Authorization: Bearer token").unwrap();
+        assert_eq!(cipher.open(&sealed).unwrap(), b"Authorization: Bearer token".to_vec());
+    }
+
+    #[test]
+    fn seals_with_a_fresh_nonce() {
+        let cipher = cipher();
+        assert_ne!(cipher.seal(b"same").unwrap(), cipher.seal(b"same").unwrap());
+    }
+
+    #[test]
+    fn rejects_tampered_value() {
+        let cipher = cipher();
+        let mut sealed = general_purpose::STANDARD.decode(cipher.seal(b"secret").unwrap()).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert_eq!(cipher.open(&general_purpose::STANDARD.encode(sealed)), Err("could not decrypt"));
+    }
+
+    #[test]
+    fn rejects_other_key() {
+        let sealed = cipher().seal(b"secret").unwrap();
+        assert_eq!(Cipher::new(&[8u8; 32]).unwrap().open(&sealed), Err("could not decrypt"));
+    }
+
+    #[test]
+    fn rejects_short_or_invalid_values() {
+        assert_eq!(cipher().open("AAAA"), Err("sealed value is too short"));
+        assert_eq!(cipher().open("not base64!"), Err("sealed value is not valid base64"));
+        assert!(Cipher::new(&[7u8; 16]).is_err());
+    }
+
+    #[test]
+    fn round_trips_json() {
+        let cipher = cipher();
+        let headers = vec![("X-Api-Key".to_string(), "key".to_string())];
+        let sealed = cipher.seal_json(&headers).unwrap();
+        assert_eq!(cipher.open_json::<Vec<(String, String)>>(&sealed).unwrap(), headers);
+    }
+}
@@ -0,0 +1,53 @@
+use std::{panic::{self, AssertUnwindSafe}, sync::mpsc, thread::{self, JoinHandle}};
+
+use tokio::sync::oneshot;
+
+type Job<T> = Box<dyn FnOnce(&T) + Send>;
+
+/// Owns a value which must not leave its thread, like a pdfium binding, and runs the jobs handed to it there one after another.
+pub struct ThreadExecutor<T> {
+    sender: mpsc::Sender<Job<T>>,
+    thread: JoinHandle<()>,
+}
+
+impl<T: 'static> ThreadExecutor<T> {
+    pub fn spawn<F>(name: &str, init: F) -> Result<Self, &'static str>
+        where F: FnOnce() -> Result<T, &'static str> + Send + 'static
+    {
+        let (sender, receiver) = mpsc::channel::<Job<T>>();
+        let (init_sender, init_receiver) = mpsc::channel();
+        let thread = thread::Builder::new().name(name.to_string()).spawn(move || {
+            let value = match init() {
+                Ok(value) => value,
+                Err(err) => {
+                    _ = init_sender.send(Err(err));
+                    return;
+                }
+            };
+            _ = init_sender.send(Ok(()));
+            for job in receiver {
+                // a panicking job drops its result sender, so only its caller sees the error
+                _ = panic::catch_unwind(AssertUnwindSafe(|| job(&value)));
+            }
+        }).map_err(|_| "could not spawn executor thread")?;
+        init_receiver.recv().map_err(|_| "executor thread stopped")??;
+        Ok(ThreadExecutor {
+            sender,
+            thread,
+        })
+    }
+
+    pub async fn run<F, R>(&self, job: F) -> Result<R, &'static str>
+        where F: FnOnce(&T) -> R + Send + 'static, R: Send + 'static
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.sender.send(Box::new(move |value| {
+            _ = result_sender.send(job(value));
+        })).map_err(|_| "executor thread stopped")?;
+        result_receiver.await.map_err(|_| "executor job panicked")
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.thread.is_finished()
+    }
+}
@@ -4,7 +4,7 @@ use mime::Mime;
 
 pub fn get_content_type(mime_type: Option<&str>, filename: &str) -> Mime {
     if let Some(mime_type) = mime_type {
-        if let Some(content_type) = Mime::from_str(mime_type).ok() {
+        if let Ok(content_type) = Mime::from_str(mime_type) {
             return content_type;
         }
     }
@@ -3,4 +3,11 @@ pub mod random;
 pub mod serialize;
 pub mod stream;
 pub mod mime;
-pub mod state;
\ No newline at end of file
+pub mod state;
+pub mod signature;
+pub mod cipher;
+pub mod request_hash;
+pub mod shutdown;
+pub mod blocking;
+pub mod executor;
+pub mod spill;
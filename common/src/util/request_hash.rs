@@ -0,0 +1,52 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+/// Hashes a create request independent of key order and whitespace, to recognize a repeated request.
+pub struct RequestHasher {
+    hasher: Sha256,
+}
+
+impl RequestHasher {
+    pub fn new(route: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(route.as_bytes());
+        hasher.update(b"\n");
+        RequestHasher {
+            hasher,
+        }
+    }
+
+    pub fn update_json(&mut self, request: &impl Serialize) -> Result<(), &'static str> {
+        let request = serde_json::to_value(request).map_err(|_| "request is not valid json")?;
+        let json = serde_json::to_vec(&sorted(request)).map_err(|_| "request is not valid json")?;
+        self.update(&json);
+        Ok(())
+    }
+
+    pub fn update_upload(&mut self, name: &str, bytes: &[u8]) {
+        self.update(name.as_bytes());
+        self.update(bytes);
+    }
+
+    pub fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update((bytes.len() as u64).to_be_bytes());
+        self.hasher.update(bytes);
+    }
+}
+
+fn sorted(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(entries.into_iter().map(|(key, value)| (key, sorted(value))).collect::<Map<String, Value>>())
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sorted).collect()),
+        value => value,
+    }
+}
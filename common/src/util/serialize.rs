@@ -1,5 +1,6 @@
 pub mod base64 {
-    use base64::engine::general_purpose;
+    use base64::alphabet;
+    use base64::engine::{general_purpose, DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
     use base64::Engine;
     use serde::{Deserialize, Serialize};
     use serde::{Deserializer, Serializer};
@@ -11,10 +12,12 @@ pub mod base64 {
 
     pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
         let base64 = String::deserialize(d)?;
-        general_purpose::STANDARD_NO_PAD.decode(base64.as_bytes()).map_err(|e| serde::de::Error::custom(e))
+        general_purpose::STANDARD_NO_PAD.decode(base64.as_bytes()).map_err(serde::de::Error::custom)
     }
 
-    pub fn deserialize_str<'de>(base64: String) -> Result<Vec<u8>, &'static str> {
-        general_purpose::STANDARD_NO_PAD.decode(base64).map_err(|_| "base64 err")
+    const STANDARD_ANY_PAD: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent));
+
+    pub fn deserialize_str(base64: String) -> Result<Vec<u8>, &'static str> {
+        STANDARD_ANY_PAD.decode(base64).map_err(|_| "base64 err")
     }
 }
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Triggered once on SIGTERM or ctrl-c, after which in-flight work has `grace_period` to finish.
+#[derive(Clone)]
+pub struct Shutdown {
+    token: CancellationToken,
+    pub grace_period: Duration,
+}
+
+impl Shutdown {
+    pub fn listen(grace_period: Duration) -> Self {
+        let shutdown = Shutdown {
+            token: CancellationToken::new(),
+            grace_period,
+        };
+        let token = shutdown.token.clone();
+        tokio::spawn(async move {
+            signal().await;
+            info!("Shutting down, draining for at most {:?}", grace_period);
+            token.cancel();
+        });
+        shutdown
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub async fn triggered(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Resolves when the grace period after the shutdown was triggered is over.
+    pub async fn expired(&self) {
+        self.triggered().await;
+        tokio::time::sleep(self.grace_period).await
+    }
+}
+
+#[cfg(unix)]
+async fn signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).expect("Could not listen for SIGTERM.");
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    _ = tokio::signal::ctrl_c().await;
+}
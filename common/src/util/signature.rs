@@ -0,0 +1,61 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub static TIMESTAMP_HEADER: &str = "X-Pdftransform-Timestamp";
+pub static SIGNATURE_HEADER: &str = "X-Pdftransform-Signature";
+
+static SIGNATURE_PREFIX: &str = "sha256=";
+
+/// Signs `{timestamp}.{body}` with HMAC-SHA256, formatted as `sha256=<hex>`.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mac = mac(secret, timestamp, body);
+    format!("{}{}", SIGNATURE_PREFIX, hex::encode(mac.finalize().into_bytes()))
+}
+
+pub fn verify(secret: &str, timestamp: i64, body: &[u8], signature: &str) -> bool {
+    let signature = match signature.strip_prefix(SIGNATURE_PREFIX).map(hex::decode) {
+        Some(Ok(signature)) => signature,
+        _ => return false,
+    };
+    mac(secret, timestamp, body).verify_slice(&signature).is_ok()
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static BODY: &[u8] = br#"{"id":"1"}"#;
+
+    #[test]
+    fn formats_signature_as_prefixed_hex() {
+        assert_eq!(sign("secret", 1700000000, BODY), "sha256=086f6aff7bd084c98679825129c5a64dbad88c760016d6d2c0fb123f27951d54");
+    }
+
+    #[test]
+    fn verifies_own_signature() {
+        assert!(verify("secret", 1700000000, BODY, &sign("secret", 1700000000, BODY)));
+    }
+
+    #[test]
+    fn rejects_other_secret_timestamp_or_body() {
+        let signature = sign("secret", 1700000000, BODY);
+        assert!(!verify("other", 1700000000, BODY, &signature));
+        assert!(!verify("secret", 1700000001, BODY, &signature));
+        assert!(!verify("secret", 1700000000, b"{}", &signature));
+    }
+
+    #[test]
+    fn rejects_malformed_signature() {
+        let signature = sign("secret", 1700000000, BODY);
+        assert!(!verify("secret", 1700000000, BODY, signature.trim_start_matches("sha256=")));
+        assert!(!verify("secret", 1700000000, BODY, "sha256=not-hex"));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::VecDeque, path::PathBuf};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use super::random::generate_30_alphanumeric;
+
+/// Output that can move its bytes out of memory into a file.
+pub trait ISpill: Sized {
+    fn spill(self, path: PathBuf) -> Result<Self, &'static str>;
+}
+
+/// Bytes held in memory or, once spilled, in a temp file.
+#[derive(Debug)]
+pub enum SpillBytes {
+    Memory(Vec<u8>),
+    File(PathBuf),
+}
+
+impl SpillBytes {
+    /// Reads spilled bytes back and removes their file.
+    pub async fn into_bytes(self) -> Result<Vec<u8>, &'static str> {
+        match self {
+            SpillBytes::Memory(bytes) => Ok(bytes),
+            SpillBytes::File(path) => {
+                let bytes = tokio::fs::read(&path).await.map_err(|_| "could not read spilled file")?;
+                _ = tokio::fs::remove_file(&path).await;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl ISpill for SpillBytes {
+    fn spill(self, path: PathBuf) -> Result<Self, &'static str> {
+        match self {
+            SpillBytes::Memory(bytes) => {
+                std::fs::write(&path, bytes).map_err(|_| "could not spill to file")?;
+                Ok(SpillBytes::File(path))
+            }
+            spilled => Ok(spilled),
+        }
+    }
+}
+
+/// Hands output of a blocking producer to an async consumer without ever waiting for it,
+/// what does not fit into the bounded channel is spilled to `directory` and queued behind it in order.
+pub struct SpillSender<T> {
+    sender: mpsc::Sender<T>,
+    directory: PathBuf,
+    spilled: VecDeque<T>,
+}
+
+impl<T: ISpill> SpillSender<T> {
+    pub fn new(sender: mpsc::Sender<T>, directory: PathBuf) -> Self {
+        SpillSender {
+            sender,
+            directory,
+            spilled: VecDeque::new(),
+        }
+    }
+
+    pub fn send(&mut self, item: T) -> Result<(), &'static str> {
+        self.flush()?;
+        if !self.spilled.is_empty() {
+            return self.spill(item);
+        }
+        match self.sender.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) => self.spill(item),
+            Err(TrySendError::Closed(_)) => Err("receiver was dropped"),
+        }
+    }
+
+    /// Closes the channel and returns what is still spilled, it comes after everything received from the channel.
+    pub fn into_spilled(self) -> Vec<T> {
+        self.spilled.into()
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        while let Some(item) = self.spilled.pop_front() {
+            match self.sender.try_send(item) {
+                Ok(()) => {}
+                Err(TrySendError::Full(item)) => {
+                    self.spilled.push_front(item);
+                    break;
+                }
+                Err(TrySendError::Closed(_)) => return Err("receiver was dropped"),
+            }
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self, item: T) -> Result<(), &'static str> {
+        let item = item.spill(self.directory.join(generate_30_alphanumeric()))?;
+        self.spilled.push_back(item);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spills_what_does_not_fit_in_order() {
+        let directory = std::env::temp_dir();
+        let (sender, mut receiver) = mpsc::channel(1);
+        let mut sender = SpillSender::new(sender, directory);
+        for bytes in [b"a", b"b", b"c"] {
+            sender.send(SpillBytes::Memory(bytes.to_vec())).unwrap();
+        }
+        let spilled = sender.into_spilled();
+        assert!(spilled.iter().all(|bytes| matches!(bytes, SpillBytes::File(_))));
+
+        let mut received = Vec::new();
+        while let Some(bytes) = receiver.recv().await {
+            received.push(bytes.into_bytes().await.unwrap());
+        }
+        for bytes in spilled {
+            received.push(bytes.into_bytes().await.unwrap());
+        }
+        assert_eq!(received, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn sends_spilled_first_once_there_is_room() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let mut sender = SpillSender::new(sender, std::env::temp_dir());
+        sender.send(SpillBytes::Memory(b"a".to_vec())).unwrap();
+        sender.send(SpillBytes::Memory(b"b".to_vec())).unwrap();
+        assert_eq!(receiver.recv().await.unwrap().into_bytes().await.unwrap(), b"a".to_vec());
+        sender.send(SpillBytes::Memory(b"c".to_vec())).unwrap();
+        assert_eq!(receiver.recv().await.unwrap().into_bytes().await.unwrap(), b"b".to_vec());
+        let spilled = sender.into_spilled();
+        assert_eq!(spilled.len(), 1);
+        assert_eq!(spilled.into_iter().next().unwrap().into_bytes().await.unwrap(), b"c".to_vec());
+    }
+
+    #[test]
+    fn fails_once_the_receiver_is_dropped() {
+        let (sender, receiver) = mpsc::channel(1);
+        drop(receiver);
+        let mut sender = SpillSender::new(sender, std::env::temp_dir());
+        assert_eq!(sender.send(SpillBytes::Memory(Vec::new())).unwrap_err(), "receiver was dropped");
+    }
+}
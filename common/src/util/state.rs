@@ -1,24 +1,34 @@
 use std::{sync::Arc, time::Duration};
 
-use crate::{persistence::{IJobPersistence, IFileStorage, s3::S3FileStorage}, nats::{base::BaseJetStream, kv_store::KeyValueStoreService}};
+use crate::{health::IReadinessCheck, persistence::{IApiKeyPersistence, IJobPersistence, IFileStorage, s3::S3FileStorage}, nats::{base::BaseJetStream, kv_store::KeyValueStoreService, quota::{IQuotaService, QuotaService}}};
 
 pub struct NatsBaseSettings<'a> {
     pub nats_uri: &'a str,
     pub bucket: String,
+    pub api_key_bucket: String,
+    pub quota_bucket: String,
     pub max_age: Duration,
 }
 
 pub struct NatsBaseServiceCollection {
     pub base_jetstream: Arc<BaseJetStream>,
     pub job_persistence: Arc<dyn IJobPersistence>,
+    pub job_bucket_check: Arc<dyn IReadinessCheck>,
+    pub api_key_persistence: Arc<dyn IApiKeyPersistence>,
+    pub quota_service: Arc<dyn IQuotaService>,
 }
 
 impl NatsBaseServiceCollection {
     pub async fn build(nats_settings: &NatsBaseSettings<'_>) -> Result<Arc<Self>, &'static str> {
         let base_jetstream = Arc::new(BaseJetStream::build(nats_settings.nats_uri).await?);
+        let job_bucket = Arc::new(KeyValueStoreService::build(base_jetstream.clone(), nats_settings.bucket.clone(), nats_settings.max_age).await?);
+        let job_persistence: Arc<dyn IJobPersistence> = job_bucket.clone();
         Ok(Arc::new(NatsBaseServiceCollection{
-            job_persistence: Arc::new(KeyValueStoreService::build(base_jetstream.clone(), nats_settings.bucket.clone(), nats_settings.max_age).await?),
-            base_jetstream: base_jetstream
+            quota_service: Arc::new(QuotaService::build(base_jetstream.clone(), nats_settings.quota_bucket.clone(), job_persistence.clone()).await?),
+            job_persistence,
+            job_bucket_check: job_bucket,
+            api_key_persistence: Arc::new(KeyValueStoreService::build(base_jetstream.clone(), nats_settings.api_key_bucket.clone(), Duration::ZERO).await?),
+            base_jetstream
         }))
     }
 }
@@ -35,16 +45,25 @@ pub struct S3BaseSettings {
 pub struct StorageBaseServiceCollection {
     pub base_jetstream: Arc<BaseJetStream>,
     pub job_persistence: Arc<dyn IJobPersistence>,
+    pub job_bucket_check: Arc<dyn IReadinessCheck>,
+    pub api_key_persistence: Arc<dyn IApiKeyPersistence>,
+    pub quota_service: Arc<dyn IQuotaService>,
     pub file_storage: Arc<dyn IFileStorage>,
+    pub file_storage_check: Arc<dyn IReadinessCheck>,
 }
 
 impl StorageBaseServiceCollection {
     pub async fn build(nats_settings: &NatsBaseSettings<'_>, s3_settings: S3BaseSettings) -> Result<Arc<Self>, &'static str> {
         let nats_base = NatsBaseServiceCollection::build(nats_settings).await?;
+        let file_storage = Arc::new(S3FileStorage::build(s3_settings.endpoint, s3_settings.region, s3_settings.access_key_id, s3_settings.secret_access_key, s3_settings.bucket, s3_settings.expire_seconds).await?);
         Ok(Arc::new(StorageBaseServiceCollection {
             base_jetstream: nats_base.base_jetstream.clone(),
             job_persistence: nats_base.job_persistence.clone(),
-            file_storage: Arc::new(S3FileStorage::build(s3_settings.endpoint, s3_settings.region, s3_settings.access_key_id, s3_settings.secret_access_key, s3_settings.bucket, s3_settings.expire_seconds).await?),
+            job_bucket_check: nats_base.job_bucket_check.clone(),
+            api_key_persistence: nats_base.api_key_persistence.clone(),
+            quota_service: nats_base.quota_service.clone(),
+            file_storage: file_storage.clone(),
+            file_storage_check: file_storage,
         }))
     }
 }
@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
-use common::convert::BaseConvertService;
+use bytes::Bytes;
+use common::convert::{BaseConvertService, ProgressReporter};
+use common::error::{Error, ErrorCode};
 use common::models::PreviewJobModel;
-use common::nats::subscribe::{IWorkerService, WorkError};
+use common::nats::reply_subscribe::IReplyWorkerService;
+use common::nats::subscribe::{IWorkerService, WorkError, IHeartbeat, Delivery};
 use tracing::info;
 
 use common::download::IDownloadService;
@@ -18,31 +21,92 @@ pub struct ConvertService {
 
 #[async_trait::async_trait]
 impl IWorkerService for ConvertService {
-    #[tracing::instrument(skip(self))]
-    async fn work(&self, job_id: &str) -> Result<(), WorkError> {
+    #[tracing::instrument(skip(self, heartbeat))]
+    async fn work(&self, job_id: &str, delivery: Delivery, heartbeat: Arc<dyn IHeartbeat>) -> Result<(), WorkError> {
         info!("Starting job");
-        let job_model = self.base.job_persistence.get(&job_id).await;
-        if let Ok(Some(job_model)) = job_model {
-            let mut job_model = PreviewJobModel::from_json_slice(&job_model).map_err(|_| WorkError::NoRetry)?;
-            let client = reqwest::Client::builder().danger_accept_invalid_certs(true).build().unwrap();
-            let job_files = TempJobFileProvider::build(&job_id).await;
-            let source_file = self.download_service.download_source_bytes(&client, &job_model.input.source_uri).await;
-            info!("Downloaded file for job");
-
-            match source_file {
-                Ok(source_file) => {
-                    let result: Result<_, &str> = self.preview_service.get_preview(&job_model, source_file.to_vec()).await;
-                    match result {
-                        Ok(result) => self.base.ready(&mut job_model, &client, result).await,
-                        Err(err) => self.base.error(&mut job_model, &client, err).await,
-                    };
-                }
-                Err(err) => {
-                    self.base.error(&mut job_model, &client, err).await;
+        let job_model = match self.base.job_persistence.get(job_id).await {
+            Ok(Some(job_model)) => job_model,
+            Ok(None) => return Ok(()),
+            // the job bucket is not reachable, the message is redelivered with backoff
+            Err(_) => return Err(WorkError::Retry),
+        };
+        let mut job_model = PreviewJobModel::from_json_slice(&job_model).map_err(|_| WorkError::NoRetry)?;
+        self.base.start(&mut job_model).await?;
+        let progress = self.base.progress(&job_model, Some(heartbeat));
+        let client = reqwest::Client::builder().danger_accept_invalid_certs(true).build().unwrap();
+        let job_files = TempJobFileProvider::build(job_id).await;
+        let result = self.convert(&mut job_model, &client, &job_files, delivery, progress).await;
+        job_files.clean_up().await;
+        result
+    }
+}
+
+impl ConvertService {
+    async fn convert(&self, job_model: &mut PreviewJobModel, client: &reqwest::Client, job_files: &TempJobFileProvider, delivery: Delivery, progress: ProgressReporter) -> Result<(), WorkError> {
+        let source_file = match &job_model.input.source_uri {
+            Some(source_uri) => self.download_service.download_source_bytes(client, source_uri).await,
+            None => Err(Error::permanent(ErrorCode::Download, "Job has no source uri.")),
+        };
+        info!("Downloaded file for job");
+        self.base.ensure_not_cancelled(&job_model.id).await?;
+
+        match source_file {
+            Ok(source_file) => {
+                self.base.count_source_bytes(job_model, source_file.len() as u64).await;
+                let result = self.preview_service.get_preview(job_model, source_file.to_vec(), job_files, &progress).await;
+                job_model.progress = Some(progress.stop().await);
+                match result {
+                    Ok(result) => self.base.ready(job_model, result).await?,
+                    Err(err) => {
+                        // files stored before the failure stay on the job, also when it is retried or was cancelled
+                        job_model.add_files(err.files);
+                        if delivery.should_retry(&err.error) {
+                            return self.base.retry(job_model, err.error).await;
+                        }
+                        self.base.error(job_model, err.error).await?;
+                    }
+                };
+            }
+            Err(err) => {
+                progress.stop().await;
+                if delivery.should_retry(&err) {
+                    return self.base.retry(job_model, err).await;
                 }
+                self.base.error(job_model, err).await?;
             }
-            job_files.clean_up().await;
         }
         Ok(())
     }
 }
+
+pub struct SyncConvertService {
+    pub base: Arc<BaseConvertService>,
+    pub preview_service: Arc<dyn IPreviewService>,
+}
+
+#[async_trait::async_trait]
+impl IReplyWorkerService for SyncConvertService {
+    #[tracing::instrument(skip(self, source_file))]
+    async fn work(&self, job_id: &str, source_file: Bytes) -> Result<Bytes, &'static str> {
+        info!("Starting sync job");
+        let job_model = self.base.job_persistence.get(job_id).await.map_err(|_| "Could not get job.")?.ok_or("Could not find job.")?;
+        let mut job_model = PreviewJobModel::from_json_slice(&job_model)?;
+        self.base.start(&mut job_model).await.map_err(|_| "Could not start job.")?;
+        let progress = self.base.progress(&job_model, None);
+
+        let job_files = TempJobFileProvider::build(job_id).await;
+        let result = self.preview_service.get_preview(&job_model, source_file.to_vec(), &job_files, &progress).await;
+        job_files.clean_up().await;
+        job_model.progress = Some(progress.stop().await);
+        let stored = match result {
+            Ok(result) => self.base.ready(&mut job_model, result).await,
+            Err(err) => {
+                job_model.add_files(err.files);
+                self.base.error(&mut job_model, err.error).await
+            }
+        };
+        stored.map_err(|_| "Could not store job.")?;
+        let json = serde_json::to_vec(&job_model.to_dto()).map_err(|_| "job is not valid json")?;
+        Ok(json.into())
+    }
+}
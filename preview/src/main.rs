@@ -1,8 +1,7 @@
-use std::{env, time::Duration};
+use std::{env, thread, time::Duration};
 
-use common::util::state::{NatsBaseSettings, S3BaseSettings};
-use pdfium_render::prelude::Pdfium;
-use preview::{preview::init_pdfium, state::ServiceCollection};
+use common::util::{cipher::Cipher, executor::ThreadExecutor, shutdown::Shutdown, state::{NatsBaseSettings, S3BaseSettings}};
+use preview::{preview::{spawn_pdfium, PdfiumPreviewer}, state::ServiceCollection};
 
 #[tokio::main]
 async fn main() {
@@ -14,14 +13,25 @@ async fn main() {
     let consumer = get_consumer();
     let consumer_ack_wait = get_consumer_ack_wait();
     let bucket = get_bucket();
+    let api_key_bucket = get_api_key_bucket();
+    let quota_bucket = get_quota_bucket();
     let max_age = get_max_age();
     let max_deliver = get_max_deliver();
+    let callback_max_age = get_callback_max_age();
+    let callback_secret = get_callback_secret();
+    let callback_headers_cipher = get_callback_headers_cipher();
     let parallelism = get_parallelism();
+    let concurrency = get_concurrency();
+    let blocking_threads = get_blocking_threads();
+    let probe_port = get_probe_port();
+    let shutdown_grace_period = get_shutdown_grace_period();
     let pdfium = get_pdfium();
 
     let nats_settings = NatsBaseSettings {
         nats_uri: &nats_uri,
         bucket,
+        api_key_bucket,
+        quota_bucket,
         max_age,
     };
 
@@ -29,9 +39,11 @@ async fn main() {
 
     let subjects = vec![format!("{}.*", &stream)];
     let filter = vec![format!("{}.{}", &stream, &consumer)];
+    let sync_subject = format!("{}.{}.sync", &stream, &consumer);
 
-    let worker = ServiceCollection::build(nats_settings, stream, subjects, parallelism, pdfium, s3_settings, consumer, filter, max_deliver, consumer_ack_wait).await.unwrap();
-    worker.subscribe_service.subscribe().await.unwrap();
+    let worker = ServiceCollection::build(nats_settings, stream, subjects, parallelism, concurrency, blocking_threads, pdfium, s3_settings, consumer, filter, max_deliver, consumer_ack_wait, callback_max_age, callback_secret, callback_headers_cipher, sync_subject).await.unwrap();
+    let shutdown = Shutdown::listen(shutdown_grace_period);
+    tokio::try_join!(common::health::serve(probe_port, worker.readiness_service.clone(), shutdown.clone()), worker.subscribe_service.subscribe(shutdown.clone()), worker.callback_subscribe_service.subscribe(shutdown.clone()), worker.reply_subscribe_service.subscribe(shutdown.clone()), worker.dlq_subscribe_service.subscribe(shutdown)).unwrap();
 }
 
 fn get_nats() -> String {
@@ -50,6 +62,14 @@ fn get_bucket() -> String {
     env::var("NATS_KV_STORE_BUCKET").unwrap_or_else(|_| "job".to_string())
 }
 
+fn get_api_key_bucket() -> String {
+    env::var("NATS_KV_API_KEY_BUCKET").unwrap_or_else(|_| "api_key".to_string())
+}
+
+fn get_quota_bucket() -> String {
+    env::var("NATS_KV_QUOTA_BUCKET").unwrap_or_else(|_| "quota".to_string())
+}
+
 fn get_max_age() -> Duration {
     let max_age = env::var("MAX_AGE_SECONDS").map(|expire| expire.parse::<u64>());
 
@@ -79,6 +99,24 @@ fn get_consumer_ack_wait() -> Duration {
     Duration::from_secs(consumer_ack_wait)
 }
 
+fn get_callback_max_age() -> Duration {
+    let callback_max_age = env::var("CALLBACK_MAX_AGE_SECONDS").map(|expire| expire.parse::<u64>());
+
+    let callback_max_age = match callback_max_age {
+        Ok(Ok(callback_max_age)) => callback_max_age,
+        _ => 60 * 60 * 24,
+    };
+    Duration::from_secs(callback_max_age)
+}
+
+fn get_callback_secret() -> Option<String> {
+    env::var("CALLBACK_SECRET").ok().filter(|secret| !secret.is_empty())
+}
+
+fn get_callback_headers_cipher() -> Option<Cipher> {
+    env::var("CALLBACK_HEADERS_KEY").ok().map(|key| Cipher::from_base64(&key).expect("CALLBACK_HEADERS_KEY must be a base64 encoded 32 byte key"))
+}
+
 fn get_parallelism() -> usize {
     let parallelism = env::var("PARALLELISM").map(|expire| expire.parse::<usize>());
     match parallelism {
@@ -87,8 +125,46 @@ fn get_parallelism() -> usize {
     }
 }
 
-fn get_pdfium() -> Pdfium {
-    init_pdfium().unwrap()
+fn get_concurrency() -> usize {
+    let concurrency = env::var("JOB_CONCURRENCY").map(|concurrency| concurrency.parse::<usize>());
+    match concurrency {
+        Ok(Ok(concurrency)) if concurrency > 0 => concurrency,
+        _ => available_parallelism(),
+    }
+}
+
+fn get_blocking_threads() -> usize {
+    let blocking_threads = env::var("BLOCKING_THREADS").map(|blocking_threads| blocking_threads.parse::<usize>());
+    match blocking_threads {
+        Ok(Ok(blocking_threads)) if blocking_threads > 0 => blocking_threads,
+        _ => available_parallelism(),
+    }
+}
+
+fn available_parallelism() -> usize {
+    thread::available_parallelism().map(|parallelism| parallelism.get()).unwrap_or(1)
+}
+
+fn get_probe_port() -> u16 {
+    let probe_port = env::var("PROBE_PORT").map(|port| port.parse::<u16>());
+    match probe_port {
+        Ok(Ok(probe_port)) => probe_port,
+        _ => 8000,
+    }
+}
+
+fn get_shutdown_grace_period() -> Duration {
+    let grace_period = env::var("SHUTDOWN_GRACE_SECONDS").map(|grace_period| grace_period.parse::<u64>());
+
+    let grace_period = match grace_period {
+        Ok(Ok(grace_period)) => grace_period,
+        _ => 25,
+    };
+    Duration::from_secs(grace_period)
+}
+
+fn get_pdfium() -> ThreadExecutor<PdfiumPreviewer> {
+    spawn_pdfium().unwrap()
 }
 
 fn get_s3_settings(max_age: Duration) -> S3BaseSettings {
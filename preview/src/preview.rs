@@ -1,13 +1,19 @@
-use std::{sync::Arc, io::Cursor};
+use std::{sync::Arc, io::Cursor, path::PathBuf};
 
-use image::ImageFormat;
+use image::{ImageFormat, RgbaImage};
 use pdfium_render::{
     prelude::{PdfDocument, Pdfium},
     render_config::PdfRenderConfig,
 };
+use tokio::sync::mpsc;
 
 use common::{
-    models::{PreviewAttachmentResult, PreviewPageResult, PreviewResult, PreviewSignature, PreviewJobModel}, persistence::IFileStorage,
+    convert::ProgressReporter,
+    error::{Error, ErrorCode},
+    health::IReadinessCheck,
+    metrics::PDFIUM_DURATION,
+    models::{PreviewAttachmentResult, PreviewInput, PreviewPageResult, PreviewResult, PreviewSignature, PreviewJobModel, StoredError, StoredResult}, persistence::{IFileStorage, tempfiles::TempJobFileProvider},
+    util::{blocking::BlockingPool, executor::ThreadExecutor, spill::{ISpill, SpillBytes, SpillSender}},
 };
 
 #[cfg(feature = "static")]
@@ -22,135 +28,212 @@ pub fn init_pdfium() -> Result<Pdfium, &'static str> {
 
 #[async_trait::async_trait]
 pub trait IPreviewService: Send + Sync {
-    async fn get_preview(&self, job: &PreviewJobModel, source_file: Vec<u8>) -> Result<PreviewResult, &'static str>;
+    async fn get_preview(&self, job: &PreviewJobModel, source_file: Vec<u8>, job_files: &TempJobFileProvider, progress: &ProgressReporter) -> Result<StoredResult<PreviewResult>, StoredError>;
+}
+
+pub fn spawn_pdfium() -> Result<ThreadExecutor<PdfiumPreviewer>, &'static str> {
+    ThreadExecutor::spawn("pdfium", || Ok(PdfiumPreviewer { pdfium: init_pdfium()? }))
 }
 
 pub struct PreviewService {
     pub storage: Arc<dyn IFileStorage>,
-    pub pdfium: Pdfium,
+    pub pdfium: ThreadExecutor<PdfiumPreviewer>,
+    pub blocking_pool: BlockingPool,
 }
 
 #[async_trait::async_trait]
 impl IPreviewService for PreviewService {
-    async fn get_preview(&self, job: &PreviewJobModel, source_file: Vec<u8>) -> Result<PreviewResult, &'static str> {
-        let results: (usize, Option<_>, Option<Vec<_>>, Option<Vec<_>>, Option<Vec<_>>, bool) = {
-            let job_id = &job.id;
-
-            let document = self.pdfium.load_pdf_from_byte_vec(source_file, None).map_err(|_| "Could not open document.")?;
-            let page_count = document.pages().len() as usize;
-            let pages = match job.input.png {
-                true => {
-                    let render_config = PdfRenderConfig::new();
-                    Some(document
-                        .pages()
-                        .iter()
-                        .enumerate()
-                        .map(|(index, page)| -> Result<_, &'static str> {
-                            let mut bytes: Vec<u8> = Vec::new();
-                            page.render_with_config(&render_config)
-                                .map_err(|_| "Could not render to image.")?
-                                .as_image()
-                                .as_rgba8()
-                                .ok_or("Could not render image.")?
-                                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
-                                .map_err(|_| "Could not save image.")?;
-                            let page_number = format!("{}", index + 1);
-                            let text = page.text().map_err(|_| "")?.all();
-
-                            Ok(async move {
-                                let file_url = self.storage.store_result_file(&format!("{}-{}", &job_id, &page_number), &format!("{}.png", page_number), Some("image/png"), bytes).await?;
-                                Ok::<PreviewPageResult, &'static str>(PreviewPageResult {
-                                    download_url: file_url,
-                                    text,
-                                })
-                            })
-                        })
-                        .collect())
-                    },
-                false => None,
-            };
-
-            let attachments = match job.input.png {
-                true => {
-                    Some(document
-                        .attachments()
-                        .iter()
-                        .map(|attachment| -> Result<_, &'static str> {
-                            let name = attachment.name();
-                            let bytes = attachment.save_to_bytes().map_err(|_| "Could not save attachment.")?;
-        
-                            Ok(async move {
-                                let file_url = self.storage.store_result_file(&format!("{}-{}", &job_id, &name), &name, None, bytes).await?;
-                                Ok::<PreviewAttachmentResult, &'static str>(PreviewAttachmentResult {
-                                    name,
-                                    download_url: file_url,
-                                })
-                            })
-                        })
-                        .collect())
-                    },
-                false => None,
-            };
-
-            let signatures = match job.input.signatures {
-                true => Some(self.signatures(&document)),
-                false => None,
-            };
-
-            let protected = self.is_protected(&document).unwrap_or(false);
-
-            let download_url = match job.input.pdf {
-                true => Some(async move {
-                    let file_url = self.storage.store_result_file(&job_id, "input.pdf", Some("application/pdf"), document.save_to_bytes().map_err(|_| "could not save")?).await?;
-                    Ok::<_, &'static str>(file_url)
-                }),
-                false => None,
-            };
-            
-            (page_count, download_url, pages, attachments, signatures, protected)
+    async fn get_preview(&self, job: &PreviewJobModel, source_file: Vec<u8>, job_files: &TempJobFileProvider, progress: &ProgressReporter) -> Result<StoredResult<PreviewResult>, StoredError> {
+        let mut files = Vec::new();
+        match self.preview(job, source_file, job_files, progress, &mut files).await {
+            Ok(result) => Ok(StoredResult {
+                result,
+                files,
+            }),
+            Err(error) => Err(StoredError { error, files }),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IReadinessCheck for PreviewService {
+    async fn check(&self) -> Result<(), String> {
+        // jobs queue up behind each other on the pdfium thread, so only its liveness is checked
+        if self.pdfium.is_running() {
+            Ok(())
+        } else {
+            Err("Pdfium thread stopped.".to_string())
+        }
+    }
+}
+
+impl PreviewService {
+    /// Records the key of every stored file in `files` right away, so they are known even if a later step fails.
+    async fn preview(&self, job: &PreviewJobModel, source_file: Vec<u8>, job_files: &TempJobFileProvider, progress: &ProgressReporter, files: &mut Vec<String>) -> Result<PreviewResult, Error> {
+        let job_id = &job.id;
+        // pages are encoded and uploaded while pdfium renders the next ones, a failed upload drops the receiver and stops the rendering
+        let (sender, receiver) = mpsc::channel::<Rendered>(RENDERED_BUFFER);
+        let rendering = {
+            let input = job.input.clone();
+            let sender = SpillSender::new(sender, job_files.directory());
+            self.pdfium.run(move |previewer| previewer.render(source_file, &input, sender))
+        };
+        let mut pages = Vec::new();
+        let mut attachments = Vec::new();
+        let stored = async {
+            // owned by the upload, so a failed upload closes the channel
+            let mut receiver = receiver;
+            while let Some(rendered) = receiver.recv().await {
+                self.store_rendered(job_id, rendered, progress, &mut pages, &mut attachments, files).await?;
+            }
+            Ok::<_, Error>(())
         };
+        let (rendering, stored) = tokio::join!(rendering, stored);
+        stored?;
+        // a stopped pdfium thread is not the fault of the document, the job is retried and readiness fails
+        let mut rendering = rendering.map_err(|err| Error::transient(ErrorCode::Executor, "Pdfium did not run the rendering.").with_source(err))??;
+        for rendered in std::mem::take(&mut rendering.spilled) {
+            self.store_rendered(job_id, rendered, progress, &mut pages, &mut attachments, files).await?;
+        }
 
-        let pages = match results.2 {
+        let pdf = match rendering.pdf {
             None => None,
-            Some(pages) => {
-                let mut ready_pages = Vec::with_capacity(pages.len());
-                for result in pages {
-                    let value = result?.await?;
-                    ready_pages.push(value);
-                }
-                Some(ready_pages)
+            Some(bytes) => {
+                progress.ensure_not_cancelled()?;
+                let file_url = self.storage.store_result_file(job_id, "input.pdf", Some("application/pdf"), bytes).await?;
+                files.push(job.id.clone());
+                Some(file_url)
             }
         };
 
-        let attachments = match results.3 {
-            None => None,
-            Some(attachments) => {
-                let mut ready_attachments = Vec::with_capacity(attachments.len());
-                for result in attachments {
-                    let value = result?.await?;
-                    ready_attachments.push(value);
-                }
-                Some(ready_attachments)
+        Ok(PreviewResult {
+            page_count: rendering.page_count,
+            pages: job.input.png.then_some(pages),
+            attachments: job.input.png.then_some(attachments),
+            pdf,
+            signatures: rendering.signatures,
+            protected: rendering.protected,
+        })
+    }
+
+    async fn store_rendered(&self, job_id: &str, rendered: Rendered, progress: &ProgressReporter, pages: &mut Vec<PreviewPageResult>, attachments: &mut Vec<PreviewAttachmentResult>, files: &mut Vec<String>) -> Result<(), Error> {
+        match rendered {
+            Rendered::PageCount(page_count) => progress.set_total(page_count),
+            Rendered::Page { index, width, height, pixels, text } => {
+                progress.ensure_not_cancelled()?;
+                let pixels = pixels.into_bytes().await.map_err(|err| Error::transient(ErrorCode::Render, format!("Could not read image of page {}.", index + 1)).with_source(err))?;
+                // encoding does not need pdfium, so pages of many jobs are encoded in parallel
+                let bytes = self.blocking_pool.run(move || encode_png(width, height, pixels, index)).await.map_err(|err| Error::permanent(ErrorCode::Render, format!("Could not save image of page {}.", index + 1)).with_source(err))??;
+                let page_number = format!("{}", index + 1);
+                let key = format!("{}-{}", job_id, &page_number);
+                let file_url = self.storage.store_result_file(&key, &format!("{}.png", page_number), Some("image/png"), bytes).await?;
+                pages.push(PreviewPageResult {
+                    download_url: file_url,
+                    text,
+                });
+                files.push(key);
+                progress.advance();
+            }
+            Rendered::Attachment { name, bytes } => {
+                progress.ensure_not_cancelled()?;
+                let bytes = bytes.into_bytes().await.map_err(|err| Error::transient(ErrorCode::Attachment, format!("Could not read attachment '{}'.", &name)).with_source(err))?;
+                let key = format!("{}-{}", job_id, &name);
+                let file_url = self.storage.store_result_file(&key, &name, None, bytes).await?;
+                attachments.push(PreviewAttachmentResult {
+                    name,
+                    download_url: file_url,
+                });
+                files.push(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of rendered pages kept in memory for their upload, later ones are spilled to the job directory.
+const RENDERED_BUFFER: usize = 2;
+
+enum Rendered {
+    PageCount(usize),
+    Page { index: usize, width: u32, height: u32, pixels: SpillBytes, text: String },
+    Attachment { name: String, bytes: SpillBytes },
+}
+
+impl ISpill for Rendered {
+    fn spill(self, path: PathBuf) -> Result<Self, &'static str> {
+        match self {
+            Rendered::Page { index, width, height, pixels, text } => Ok(Rendered::Page { index, width, height, pixels: pixels.spill(path)?, text }),
+            Rendered::Attachment { name, bytes } => Ok(Rendered::Attachment { name, bytes: bytes.spill(path)? }),
+            page_count => Ok(page_count),
+        }
+    }
+}
+
+struct Rendering {
+    page_count: usize,
+    signatures: Option<Vec<PreviewSignature>>,
+    protected: bool,
+    pdf: Option<Vec<u8>>,
+    /// Pages and attachments that did not fit the channel, they follow everything sent through it.
+    spilled: Vec<Rendered>,
+}
+
+/// Owns the pdfium binding on the thread of its executor, pdfium is not thread safe.
+pub struct PdfiumPreviewer {
+    pdfium: Pdfium,
+}
+
+impl PdfiumPreviewer {
+    /// Never waits for the uploads, so a slow upload does not hold the pdfium thread.
+    fn render(&self, source_file: Vec<u8>, input: &PreviewInput, mut sender: SpillSender<Rendered>) -> Result<Rendering, Error> {
+        let _timer = PDFIUM_DURATION.with_label_values(&["preview"]).start_timer();
+        let mut send = |rendered: Rendered| sender.send(rendered).map_err(|err| Error::permanent(ErrorCode::Render, "Rendering was stopped.").with_source(err));
+
+        let document = self.pdfium.load_pdf_from_byte_vec(source_file, None).map_err(|err| Error::permanent(ErrorCode::InvalidDocument, "Could not open document.").with_source(err))?;
+        let page_count = document.pages().len() as usize;
+        if input.png {
+            send(Rendered::PageCount(page_count))?;
+            let render_config = PdfRenderConfig::new();
+            for (index, page) in document.pages().iter().enumerate() {
+                let render_timer = PDFIUM_DURATION.with_label_values(&["render_page"]).start_timer();
+                let image = page.render_with_config(&render_config)
+                    .map_err(|err| Error::permanent(ErrorCode::Render, format!("Could not render page {} to image.", index + 1)).with_source(err))?
+                    .as_image()
+                    .into_rgba8();
+                render_timer.observe_duration();
+                let text = page.text().map_err(|err| Error::permanent(ErrorCode::Render, format!("Could not extract text of page {}.", index + 1)).with_source(err))?.all();
+                let (width, height) = image.dimensions();
+                send(Rendered::Page { index, width, height, pixels: SpillBytes::Memory(image.into_raw()), text })?;
+            }
+
+            for attachment in document.attachments().iter() {
+                let name = attachment.name();
+                let bytes = attachment.save_to_bytes().map_err(|err| Error::permanent(ErrorCode::Attachment, format!("Could not save attachment '{}'.", &name)).with_source(err))?;
+                send(Rendered::Attachment { name, bytes: SpillBytes::Memory(bytes) })?;
             }
+        }
+
+        let signatures = match input.signatures {
+            true => Some(self.signatures(&document)),
+            false => None,
         };
 
-        let pdf = match results.1 {
-            None => None,
-            Some(url) => Some(url.await?)
+        let protected = self.is_protected(&document).unwrap_or(false);
+
+        let pdf = match input.pdf {
+            true => Some(document.save_to_bytes().map_err(|err| Error::permanent(ErrorCode::Save, "Could not save document.").with_source(err))?),
+            false => None,
         };
 
-        Ok(PreviewResult {
-            page_count: results.0,
-            pages,
-            attachments,
+        Ok(Rendering {
+            page_count,
+            signatures,
+            protected,
             pdf,
-            signatures: results.4,
-            protected: results.5,
+            spilled: sender.into_spilled(),
         })
     }
-}
 
-impl PreviewService {
     fn is_protected(&self, document: &PdfDocument) -> Result<bool, &'static str> {
         let permissions = document.permissions();
         let protected = !permissions.can_add_or_modify_text_annotations().map_err(|_| "Could not determine permissions.")?
@@ -174,3 +257,12 @@ impl PreviewService {
             .collect()
     }
 }
+
+fn encode_png(width: u32, height: u32, pixels: Vec<u8>, index: usize) -> Result<Vec<u8>, Error> {
+    let mut bytes: Vec<u8> = Vec::new();
+    RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| Error::permanent(ErrorCode::Render, format!("Could not render page {} to image.", index + 1)))?
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .map_err(|err| Error::permanent(ErrorCode::Render, format!("Could not save image of page {}.", index + 1)).with_source(err))?;
+    Ok(bytes)
+}
@@ -1,6 +1,7 @@
 use axum::Router;
 use axum::error_handling::HandleErrorLayer;
-use common::util::state::NatsBaseSettings;
+use common::models::QuotaModel;
+use common::util::{cipher::Cipher, shutdown::Shutdown, state::{NatsBaseSettings, S3BaseSettings}};
 use service::state::ServiceCollection;
 use service::routes;
 use reqwest::StatusCode;
@@ -19,20 +20,39 @@ async fn main() {
     let nats_uri = get_nats();
     let stream = get_stream();
     let bucket = get_bucket();
+    let api_key_bucket = get_api_key_bucket();
+    let quota_bucket = get_quota_bucket();
     let max_age = get_max_age();
+    let sync_timeout = get_sync_timeout();
+    let sync_max_bytes = get_sync_max_bytes();
+    let upload_max_bytes = get_upload_max_bytes();
+    let idempotency_bucket = get_idempotency_bucket();
+    let idempotency_window = get_idempotency_window();
+    let admin_token = get_admin_token();
+    let require_api_key = get_require_api_key();
+    let default_quota = get_default_quota();
+    let callback_headers_cipher = get_callback_headers_cipher();
+    let shutdown_grace_period = get_shutdown_grace_period();
 
     let settings = NatsBaseSettings {
         nats_uri: &nats_uri,
         bucket,
+        api_key_bucket,
+        quota_bucket,
         max_age,
     };
 
-    let services = ServiceCollection::build(settings, stream).await.unwrap();
+    let s3_settings = get_s3_settings(max_age);
+
+    let services = ServiceCollection::build(settings, s3_settings, stream, sync_timeout, sync_max_bytes, upload_max_bytes, idempotency_bucket, idempotency_window, admin_token, require_api_key, default_quota, callback_headers_cipher).await.unwrap();
 
     let app = Router::new()
-        .merge(routes::root::create_route())
+        .merge(routes::root::create_route(services.clone()))
+        .merge(routes::openapi::create_route())
+        .merge(routes::metrics::create_route())
         .merge(routes::preview::create_route(services.clone()))
         .merge(routes::transform::create_route(services.clone()))
+        .merge(routes::admin::create_route(services.clone()))
         .layer(ServiceBuilder::new()
             .layer(TraceLayer::new_for_http())
             .layer(HandleErrorLayer::new(|_| async {
@@ -43,10 +63,15 @@ async fn main() {
 
     let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 8000);
     info!("listening on {}", &addr);
-    axum::Server::bind(&addr)
+    let shutdown = Shutdown::listen(shutdown_grace_period);
+    let server = axum::Server::bind(&addr)
         .serve(app.into_make_service())
-        .await
-        .unwrap();
+        .with_graceful_shutdown(shutdown.triggered());
+    // open event streams would otherwise keep the server alive after the grace period
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = shutdown.expired() => info!("grace period expired, dropping open connections"),
+    }
 }
 
 fn get_nats() -> String {
@@ -61,6 +86,14 @@ fn get_bucket() -> String {
     env::var("NATS_KV_STORE_BUCKET").unwrap_or_else(|_| "job".to_string())
 }
 
+fn get_api_key_bucket() -> String {
+    env::var("NATS_KV_API_KEY_BUCKET").unwrap_or_else(|_| "api_key".to_string())
+}
+
+fn get_quota_bucket() -> String {
+    env::var("NATS_KV_QUOTA_BUCKET").unwrap_or_else(|_| "quota".to_string())
+}
+
 fn get_max_age() -> Duration {
     let max_age = env::var("MAX_AGE_SECONDS").map(|expire| expire.parse::<u64>());
 
@@ -70,3 +103,84 @@ fn get_max_age() -> Duration {
     };
     Duration::from_secs(max_age)
 }
+
+fn get_sync_timeout() -> Duration {
+    let sync_timeout = env::var("SYNC_TIMEOUT_SECONDS").map(|timeout| timeout.parse::<u64>());
+
+    let sync_timeout = match sync_timeout {
+        Ok(Ok(sync_timeout)) if sync_timeout < 59 => sync_timeout,
+        _ => 50,
+    };
+    Duration::from_secs(sync_timeout)
+}
+
+fn get_sync_max_bytes() -> usize {
+    let sync_max_bytes = env::var("SYNC_MAX_BYTES").map(|max_bytes| max_bytes.parse::<usize>());
+    match sync_max_bytes {
+        Ok(Ok(sync_max_bytes)) if sync_max_bytes > 0 => sync_max_bytes,
+        _ => 1024 * 1024,
+    }
+}
+
+fn get_upload_max_bytes() -> usize {
+    let upload_max_bytes = env::var("UPLOAD_MAX_BYTES").map(|max_bytes| max_bytes.parse::<usize>());
+    match upload_max_bytes {
+        Ok(Ok(upload_max_bytes)) if upload_max_bytes > 0 => upload_max_bytes,
+        _ => 100 * 1024 * 1024,
+    }
+}
+
+fn get_idempotency_bucket() -> String {
+    env::var("NATS_KV_IDEMPOTENCY_BUCKET").unwrap_or_else(|_| "idempotency".to_string())
+}
+
+fn get_idempotency_window() -> Duration {
+    let idempotency_window = env::var("IDEMPOTENCY_WINDOW_SECONDS").map(|window| window.parse::<u64>());
+
+    let idempotency_window = match idempotency_window {
+        Ok(Ok(idempotency_window)) if idempotency_window > 0 => idempotency_window,
+        _ => 60 * 60 * 24,
+    };
+    Duration::from_secs(idempotency_window)
+}
+
+fn get_shutdown_grace_period() -> Duration {
+    let grace_period = env::var("SHUTDOWN_GRACE_SECONDS").map(|grace_period| grace_period.parse::<u64>());
+
+    let grace_period = match grace_period {
+        Ok(Ok(grace_period)) => grace_period,
+        _ => 25,
+    };
+    Duration::from_secs(grace_period)
+}
+
+fn get_admin_token() -> Option<String> {
+    env::var("ADMIN_TOKEN").ok().filter(|admin_token| !admin_token.is_empty())
+}
+
+fn get_require_api_key() -> bool {
+    env::var("REQUIRE_API_KEY").map(|require| require == "true").unwrap_or(false)
+}
+
+fn get_default_quota() -> QuotaModel {
+    QuotaModel {
+        jobs_per_minute: env::var("QUOTA_JOBS_PER_MINUTE").ok().and_then(|limit| limit.parse::<u64>().ok()),
+        max_in_flight: env::var("QUOTA_MAX_IN_FLIGHT").ok().and_then(|limit| limit.parse::<usize>().ok()),
+        source_bytes_per_day: env::var("QUOTA_SOURCE_BYTES_PER_DAY").ok().and_then(|limit| limit.parse::<u64>().ok()),
+    }
+}
+
+fn get_callback_headers_cipher() -> Option<Cipher> {
+    env::var("CALLBACK_HEADERS_KEY").ok().map(|key| Cipher::from_base64(&key).expect("CALLBACK_HEADERS_KEY must be a base64 encoded 32 byte key"))
+}
+
+fn get_s3_settings(max_age: Duration) -> S3BaseSettings {
+    S3BaseSettings {
+        endpoint: env::var("S3_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string()),
+        region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+        access_key_id: env::var("S3_ACCESS_KEY_ID").unwrap_or_else(|_| "minio123".to_string()),
+        secret_access_key: env::var("S3_SECRET_ACCESS_KEY").unwrap_or_else(|_| "minio123".to_string()),
+        bucket: env::var("S3_BUCKET").unwrap_or_else(|_| "bucket".to_string()),
+        expire_seconds: max_age.as_secs() as u32,
+    }
+}
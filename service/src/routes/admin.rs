@@ -0,0 +1,142 @@
+use axum::extract::{Path, Query};
+use axum::http::{header::AUTHORIZATION, Request};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::{
+    extract::State,
+    response::IntoResponse,
+    routing::{delete, get, post},
+};
+use axum::{Json, Router};
+use common::dtos::{ApiKeyDto, CreateApiKeyDto, ReplayDeadLettersDto, ReplayResultDto};
+use common::models::{ApiKeyModel, JobModel, JobStatus};
+use common::nats::publish::IPublishService;
+use common::util::random;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::state::Services;
+
+pub fn create_route(services: Services) -> Router {
+    Router::new()
+        .route("/admin/dlq", get(dead_letters))
+        .route("/admin/dlq/replay", post(replay_dead_letters))
+        .route("/admin/dlq/:sequence", get(dead_letter))
+        .route("/admin/api-keys", post(create_api_key))
+        .route("/admin/api-keys/:api_key_id", delete(delete_api_key))
+        .route_layer(middleware::from_fn_with_state(services.clone(), require_admin))
+        .with_state(services)
+}
+
+async fn require_admin<B>(State(services): State<Services>, request: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
+    let admin_token = services.admin_token.as_deref().ok_or(StatusCode::NOT_FOUND)?;
+    let token = request.headers().get(AUTHORIZATION).and_then(|header| header.to_str().ok()).and_then(|header| header.strip_prefix("Bearer "));
+    match token {
+        Some(token) if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) => Ok(next.run(request).await),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[tracing::instrument(skip(services))]
+pub async fn dead_letters(State(services): State<Services>, Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
+    let limit = params.get("limit").and_then(|limit| limit.parse::<usize>().ok()).unwrap_or(100);
+    let dead_letters = services.dlq_admin_service.list(limit).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    Ok::<_, (StatusCode, &str)>(Json(dead_letters.iter().map(|dead_letter| dead_letter.to_dto()).collect::<Vec<_>>()))
+}
+
+#[tracing::instrument(skip(services))]
+pub async fn dead_letter(State(services): State<Services>, Path(sequence): Path<u64>) -> impl IntoResponse {
+    match services.dlq_admin_service.get(sequence).await {
+        Ok(Some(dead_letter)) => Ok(Json(dead_letter.to_detail_dto())),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Dead letter not found.")),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+#[tracing::instrument(skip(services))]
+pub async fn replay_dead_letters(State(services): State<Services>, Json(replay): Json<ReplayDeadLettersDto>) -> impl IntoResponse {
+    let mut results = Vec::with_capacity(replay.sequences.len());
+    for sequence in replay.sequences {
+        let (job_id, result) = match services.dlq_admin_service.get(sequence).await {
+            Ok(Some(dead_letter)) => {
+                let result = replay_dead_letter(&services, dead_letter.job_id.as_deref(), dead_letter.original.as_ref().map(|original| original.subject.as_str())).await;
+                if result.is_ok() {
+                    _ = services.dlq_admin_service.delete(sequence).await;
+                }
+                (dead_letter.job_id, result)
+            }
+            Ok(None) => (None, Err("Dead letter not found.")),
+            Err(err) => (None, Err(err)),
+        };
+        results.push(ReplayResultDto {
+            sequence,
+            job_id,
+            replayed: result.is_ok(),
+            message: result.err().map(|err| err.to_string()),
+        });
+    }
+    Json(results)
+}
+
+async fn replay_dead_letter(services: &Services, job_id: Option<&str>, subject: Option<&str>) -> Result<(), &'static str> {
+    let job_id = job_id.ok_or("Original message is no longer available.")?;
+    let publish_service: Arc<dyn IPublishService> = match subject {
+        Some(subject) if subject == services.transform_publish_service.subject() => services.transform_publish_service.clone(),
+        Some(subject) if subject == services.preview_publish_service.subject() => services.preview_publish_service.clone(),
+        _ => return Err("Original subject is unknown."),
+    };
+    let entry = services.job_persistence.get_entry(job_id).await.map_err(|_| "Could not get job.")?.ok_or("Job does not exist anymore.")?;
+    let mut job: JobModel<Value, Value> = serde_json::from_slice(&entry.value).map_err(|_| "job is not valid json")?;
+    if matches!(job.status, JobStatus::Cancelled) {
+        return Err("Job was cancelled.");
+    }
+    job.status = JobStatus::Pending;
+    job.started = None;
+    job.finished = None;
+    job.message = None;
+    job.error = None;
+    job.result = None;
+    job.progress = None;
+    job.callback = None;
+    // a cancel or delete since the read must not be overwritten with a pending job
+    services.job_persistence.update(&job, entry.revision).await.map_err(|_| "Could not put job.")?.ok_or("Job changed while replaying it.")?;
+    publish_service.publish(&job.id).await
+}
+
+#[tracing::instrument(skip(services, create_api_key))]
+pub async fn create_api_key(State(services): State<Services>, Json(create_api_key): Json<CreateApiKeyDto>) -> impl IntoResponse {
+    if create_api_key.tenant.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Tenant must not be empty.".to_string()))
+    }
+    let api_key = random::generate_30_alphanumeric();
+    let api_key_id = ApiKeyModel::get_id(&api_key);
+    let model = ApiKeyModel {
+        tenant: create_api_key.tenant,
+        callback_secret: create_api_key.callback_secret,
+        quota: create_api_key.quota,
+    };
+    services.api_key_persistence.put_api_key(&api_key_id, &model).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok((StatusCode::CREATED, Json(ApiKeyDto {
+        id: api_key_id,
+        tenant: model.tenant,
+        quota: model.quota,
+        api_key: Some(api_key),
+    })))
+}
+
+#[tracing::instrument(skip(services))]
+pub async fn delete_api_key(State(services): State<Services>, Path(api_key_id): Path<String>) -> impl IntoResponse {
+    match services.api_key_persistence.get_api_key(&api_key_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Api key not found.".to_string())),
+        Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+    services.api_key_persistence.delete_api_key(&api_key_id).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
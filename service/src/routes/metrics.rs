@@ -0,0 +1,16 @@
+use axum::http::{header::CONTENT_TYPE, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use common::metrics::{self, METRICS_CONTENT_TYPE};
+
+pub fn create_route() -> Router {
+    Router::new().route("/metrics", get(render_metrics))
+}
+
+pub async fn render_metrics() -> impl IntoResponse {
+    match metrics::render() {
+        Ok(body) => Ok(([(CONTENT_TYPE, METRICS_CONTENT_TYPE)], body)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
@@ -1,5 +1,177 @@
+use std::collections::HashMap;
+
+use axum::{body::Body, extract::State, http::{header::{CONTENT_LENGTH, CONTENT_TYPE, HOST, RETRY_AFTER, TRANSFER_ENCODING}, HeaderMap, HeaderName, HeaderValue, Request, StatusCode}, middleware::Next, response::{sse::Event, ErrorResponse, IntoResponse, Response}, Json};
+use bytes::Bytes;
+use common::{dtos::{GetSelfRoute, JobDto}, models::{ApiKeyModel, IdempotencyModel, JobModel, JobStatus, QuotaModel}, nats::quota::QuotaError, util::signature::{SIGNATURE_HEADER, TIMESTAMP_HEADER}};
+use futures::{stream::{self, BoxStream}, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::state::Services;
+
+pub mod admin;
+
+pub mod metrics;
+
+pub mod openapi;
+
 pub mod preview;
 
 pub mod root;
 
 pub mod transform;
+
+pub static API_KEY_HEADER: &str = "X-Api-Key";
+
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub tenant: String,
+    pub api_key_id: String,
+    pub quota: QuotaModel,
+}
+
+/// Resolves the api key of the request to its tenant. Requests without a key pass as anonymous, unknown keys are rejected.
+pub(crate) async fn authenticate<B>(State(services): State<Services>, mut request: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
+    let api_key = request.headers().get(API_KEY_HEADER).map(|header| header.to_str().map_err(|_| StatusCode::UNAUTHORIZED)).transpose()?;
+    let caller = match api_key {
+        Some(api_key) => {
+            let api_key_id = ApiKeyModel::get_id(api_key);
+            let api_key = services.api_key_persistence.get_api_key(&api_key_id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.ok_or(StatusCode::UNAUTHORIZED)?;
+            Some(Caller {
+                tenant: api_key.tenant,
+                api_key_id,
+                quota: api_key.quota.unwrap_or_default().or(&services.default_quota),
+            })
+        }
+        None => None,
+    };
+    request.extensions_mut().insert(caller);
+    Ok(next.run(request).await)
+}
+
+pub(crate) fn require_caller(services: &Services, caller: Option<Caller>) -> Result<Option<Caller>, (StatusCode, String)> {
+    match caller {
+        None if services.require_api_key => Err((StatusCode::UNAUTHORIZED, format!("Missing {} header.", API_KEY_HEADER))),
+        caller => Ok(caller),
+    }
+}
+
+/// A job can be read with its token, which keeps links shareable, or with an api key of the tenant that created it.
+pub(crate) fn can_access(caller: &Option<Caller>, params: &HashMap<String, String>, token: &str, tenant: &Option<String>) -> bool {
+    if params.get("token").map(|param| param == token).unwrap_or(false) {
+        return true;
+    }
+    matches!((caller, tenant), (Some(caller), Some(tenant)) if &caller.tenant == tenant)
+}
+
+/// Counts the job against the quota of the calling tenant, anonymous callers are not limited.
+pub(crate) async fn admit_job(services: &Services, caller: &Option<Caller>, job_id: &str, source_bytes: u64) -> Result<(), ErrorResponse> {
+    let caller = match caller {
+        Some(caller) => caller,
+        None => return Ok(()),
+    };
+    match services.quota_service.admit(&caller.tenant, job_id, source_bytes, &caller.quota).await {
+        Ok(()) => Ok(()),
+        Err(QuotaError::Exceeded { message, retry_after }) => Err((StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, retry_after.as_secs().max(1).to_string())], message).into_response().into()),
+        Err(QuotaError::Failed(err)) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into()),
+    }
+}
+
+pub static IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+pub(crate) enum Idempotency {
+    Unclaimed,
+    Claimed(String),
+    Repeated(String),
+}
+
+impl Idempotency {
+    /// Frees the key of a request that did not create its job, so the client can retry it.
+    pub(crate) async fn release(&self, services: &Services) {
+        if let Idempotency::Claimed(id) = self {
+            _ = services.idempotency_service.release(id).await;
+        }
+    }
+}
+
+pub(crate) fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, (StatusCode, String)> {
+    match headers.get(IDEMPOTENCY_KEY_HEADER).map(|header| header.to_str()) {
+        None => Ok(None),
+        Some(Ok(idempotency_key)) if !idempotency_key.is_empty() && idempotency_key.len() <= 255 => Ok(Some(idempotency_key.to_string())),
+        Some(_) => Err((StatusCode::BAD_REQUEST, format!("{} must have 1 to 255 visible characters.", IDEMPOTENCY_KEY_HEADER))),
+    }
+}
+
+/// Claims the idempotency key for the new job, a repeated request resolves to the job created by the first one.
+pub(crate) async fn claim_idempotency_key(services: &Services, caller: &Option<Caller>, idempotency_key: Option<String>, job_id: &str, request_hash: String) -> Result<Idempotency, (StatusCode, String)> {
+    let idempotency_key = match idempotency_key {
+        Some(idempotency_key) => idempotency_key,
+        None => return Ok(Idempotency::Unclaimed),
+    };
+    let id = IdempotencyModel::get_id(caller.as_ref().map(|caller| caller.tenant.as_str()), &idempotency_key);
+    let idempotency = IdempotencyModel {
+        request_hash,
+        job_id: job_id.to_string(),
+    };
+    match services.idempotency_service.claim(&id, &idempotency).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))? {
+        None => Ok(Idempotency::Claimed(id)),
+        Some(claimed) if claimed.request_hash == idempotency.request_hash => Ok(Idempotency::Repeated(claimed.job_id)),
+        Some(_) => Err((StatusCode::CONFLICT, format!("{} was already used for a different request.", IDEMPOTENCY_KEY_HEADER))),
+    }
+}
+
+pub(crate) async fn repeated_job<InputType, ResultType>(services: &Services, job_id: &str) -> Result<Json<JobDto<ResultType>>, (StatusCode, String)>
+    where JobModel<InputType, ResultType>: GetSelfRoute + DeserializeOwned, ResultType: Clone + Serialize
+{
+    match services.job_persistence.get(job_id).await {
+        Ok(Some(job)) => {
+            let job: JobModel<InputType, ResultType> = serde_json::from_slice(&job).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "job is not valid json".to_string()))?;
+            Ok(Json(job.to_dto()))
+        }
+        Ok(None) => Err((StatusCode::CONFLICT, format!("A request with this {} is still in progress.", IDEMPOTENCY_KEY_HEADER))),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+pub(crate) fn is_multipart(request: &Request<Body>) -> bool {
+    request.headers().get(CONTENT_TYPE).and_then(|content_type| content_type.to_str().ok()).map(|content_type| content_type.starts_with("multipart/form-data")).unwrap_or(false)
+}
+
+pub(crate) fn seal_callback_headers(services: &Services, callback_headers: Option<HashMap<String, String>>) -> Result<Option<String>, (StatusCode, String)> {
+    let callback_headers = match callback_headers {
+        Some(callback_headers) if !callback_headers.is_empty() => callback_headers,
+        _ => return Ok(None),
+    };
+    let cipher = services.callback_headers_cipher.as_ref().ok_or((StatusCode::BAD_REQUEST, "Callback headers are not enabled.".to_string()))?;
+    let reserved = [CONTENT_TYPE.as_str(), CONTENT_LENGTH.as_str(), HOST.as_str(), TRANSFER_ENCODING.as_str(), TIMESTAMP_HEADER, SIGNATURE_HEADER];
+    for (name, value) in &callback_headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| (StatusCode::BAD_REQUEST, format!("Callback header '{}' is not a valid header name.", name)))?;
+        if reserved.iter().any(|reserved| reserved.eq_ignore_ascii_case(header_name.as_str())) {
+            return Err((StatusCode::BAD_REQUEST, format!("Callback header '{}' can't be overridden.", name)));
+        }
+        HeaderValue::from_str(value).map_err(|_| (StatusCode::BAD_REQUEST, format!("Callback header '{}' has no valid value.", name)))?;
+    }
+    let sealed = cipher.seal_json(&callback_headers).map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(Some(sealed))
+}
+
+pub(crate) async fn purge_job(services: &Services, job_id: &str, files: &[String]) -> Result<(), StatusCode> {
+    for key in files {
+        services.file_storage.delete_file(key).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    services.job_persistence.delete(job_id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) fn job_events<InputType, ResultType>(updates: BoxStream<'static, Bytes>) -> impl Stream<Item = Result<Event, serde_json::Error>>
+    where JobModel<InputType, ResultType>: GetSelfRoute + DeserializeOwned, ResultType: Clone + Serialize
+{
+    // the stream ends right after the terminal status instead of waiting for an update that may never come,
+    // dropping the updates then stops the watch
+    stream::unfold((updates, false), |(mut updates, finished)| async move {
+        if finished {
+            return None;
+        }
+        let job: JobModel<InputType, ResultType> = serde_json::from_slice(&updates.next().await?).ok()?;
+        let finished = !matches!(job.status, JobStatus::Pending | JobStatus::InProgress);
+        Some((Event::default().event("status").json_data(job.to_dto()), (updates, finished)))
+    })
+}
@@ -0,0 +1,236 @@
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use common::dtos::{CreatePreviewJobDto, CreateTransformJobDto, InspectResultDto, PreviewJobDto, ReadinessDto, RootDto, TransformJobDto, ValidationErrorsDto};
+use common::util::consts::{NAME, VERSION};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Value};
+
+use super::{API_KEY_HEADER, IDEMPOTENCY_KEY_HEADER};
+
+pub fn create_route() -> Router {
+    let document = openapi();
+    Router::new()
+        .route("/openapi.json", get(move || async move { Json(document) }))
+        .route("/docs", get(docs))
+}
+
+pub async fn docs() -> Html<&'static str> {
+    Html(r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>pdftransform</title>
+    <meta charset="utf-8"/>
+  </head>
+  <body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+  </body>
+</html>"#)
+}
+
+/// Generic job dtos get the name of their alias instead of the generated `JobDto_for_...`.
+fn named_schema<T: JsonSchema>(generator: &mut SchemaGenerator, name: &str) -> Value {
+    let mut schema = generator.root_schema_for::<T>().schema;
+    schema.metadata().title = None;
+    generator.definitions_mut().insert(name.to_string(), schema.into());
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn subschema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(generator.subschema_for::<T>()).unwrap_or_default()
+}
+
+fn json_content(schema: &Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn job_routes(kind: &str, job: &Value) -> (String, Value, String, Value) {
+    let job_id = json!({ "name": "jobId", "in": "path", "required": true, "schema": { "type": "string" } });
+    let token = json!({ "name": "token", "in": "query", "required": false, "description": "Token of the job, alternative to an api key of the tenant that created it.", "schema": { "type": "string" } });
+    let not_found = json!({ "description": "Job does not exist, or neither token nor api key grant access." });
+    (
+        format!("/{}/{{jobId}}", kind),
+        json!({
+            "get": {
+                "tags": [kind],
+                "summary": format!("Get the {} job", kind),
+                "parameters": [job_id, token],
+                "responses": {
+                    "200": { "description": "The job.", "content": json_content(job) },
+                    "404": not_found,
+                },
+            },
+            "delete": {
+                "tags": [kind],
+                "summary": format!("Cancel a running {} job, or purge a finished one", kind),
+                "parameters": [job_id, token, { "name": "purge", "in": "query", "required": false, "schema": { "type": "boolean" } }],
+                "responses": {
+                    "200": { "description": "The cancelled job.", "content": json_content(job) },
+                    "204": { "description": "The job and its files were purged." },
+                    "404": not_found,
+                    "409": { "description": "The job can't be cancelled or purged in its current status." },
+                },
+            },
+        }),
+        format!("/{}/{{jobId}}/events", kind),
+        json!({
+            "get": {
+                "tags": [kind],
+                "summary": format!("Stream the status of the {} job as server sent events", kind),
+                "parameters": [job_id, token],
+                "responses": {
+                    "200": { "description": "A `status` event with the job on every change, until it is finished.", "content": { "text/event-stream": { "schema": { "type": "string" } } } },
+                    "404": not_found,
+                },
+            },
+        }),
+    )
+}
+
+pub fn openapi() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let root = subschema::<RootDto>(&mut generator);
+    let create_transform_job = subschema::<CreateTransformJobDto>(&mut generator);
+    let create_preview_job = subschema::<CreatePreviewJobDto>(&mut generator);
+    let validation_errors = subschema::<ValidationErrorsDto>(&mut generator);
+    let inspect_result = subschema::<InspectResultDto>(&mut generator);
+    let readiness = subschema::<ReadinessDto>(&mut generator);
+    let transform_job = named_schema::<TransformJobDto>(&mut generator, "TransformJobDto");
+    let preview_job = named_schema::<PreviewJobDto>(&mut generator, "PreviewJobDto");
+
+    let idempotency_key = json!({ "name": IDEMPOTENCY_KEY_HEADER, "in": "header", "required": false, "description": "Repeating a request with the same key returns the job created first.", "schema": { "type": "string", "maxLength": 255 } });
+    let unauthorized = json!({ "description": "Unknown api key, or none sent while api keys are required." });
+    let too_many_requests = json!({ "description": "The quota of the tenant is exhausted, retry after the seconds of the `Retry-After` header." });
+    let invalid = json!({ "description": "The request is not valid.", "content": json_content(&validation_errors) });
+    let (transform_job_path, transform_job_item, transform_events_path, transform_events_item) = job_routes("transform", &transform_job);
+    let (preview_job_path, preview_job_item, preview_events_path, preview_events_item) = job_routes("preview", &preview_job);
+    let sync_flags: Vec<Value> = ["pdf", "png", "attachments", "signatures"].iter().map(|flag| json!({ "name": flag, "in": "query", "required": false, "schema": { "type": "boolean", "default": true } })).collect();
+
+    let mut paths = serde_json::Map::new();
+    paths.insert("/".to_string(), json!({
+        "get": {
+            "tags": ["root"],
+            "summary": "Get version and links",
+            "security": [],
+            "responses": { "200": { "description": "Version and links.", "content": json_content(&root) } },
+        },
+    }));
+    paths.insert("/health".to_string(), json!({
+        "get": {
+            "tags": ["root"],
+            "summary": "Check the service is up",
+            "security": [],
+            "responses": { "200": { "description": "The service is up." } },
+        },
+    }));
+    paths.insert("/ready".to_string(), json!({
+        "get": {
+            "tags": ["root"],
+            "summary": "Check the service can reach its dependencies",
+            "security": [],
+            "responses": {
+                "200": { "description": "All dependencies are reachable.", "content": json_content(&readiness) },
+                "503": { "description": "At least one dependency is not reachable, see `checks`.", "content": json_content(&readiness) },
+            },
+        },
+    }));
+    paths.insert("/transform".to_string(), json!({
+        "post": {
+            "tags": ["transform"],
+            "summary": "Create a transform job",
+            "parameters": [idempotency_key],
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": { "schema": create_transform_job },
+                    "multipart/form-data": {
+                        "schema": {
+                            "type": "object",
+                            "description": "The `job` part holds the request, every other part is uploaded as the source file with the id of its name.",
+                            "properties": { "job": create_transform_job },
+                            "additionalProperties": { "type": "string", "format": "binary" },
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "200": { "description": "The created job.", "content": json_content(&transform_job) },
+                "401": unauthorized,
+                "409": { "description": "The idempotency key was used for a different request, or that request is still in progress." },
+                "422": invalid,
+                "429": too_many_requests,
+            },
+        },
+    }));
+    paths.insert("/transform/inspect".to_string(), json!({
+        "post": {
+            "tags": ["transform"],
+            "summary": "Inspect the sources of a transform without creating a job",
+            "requestBody": { "required": true, "content": json_content(&create_transform_job) },
+            "responses": {
+                "200": { "description": "Page counts of sources and documents, and every range not matching them.", "content": json_content(&inspect_result) },
+                "401": unauthorized,
+                "422": invalid,
+                "503": { "description": "No transform worker available." },
+                "504": { "description": "The inspection did not finish in time." },
+            },
+        },
+    }));
+    paths.insert(transform_job_path, transform_job_item);
+    paths.insert(transform_events_path, transform_events_item);
+    paths.insert("/preview".to_string(), json!({
+        "post": {
+            "tags": ["preview"],
+            "summary": "Create a preview job",
+            "parameters": [idempotency_key],
+            "requestBody": { "required": true, "content": json_content(&create_preview_job) },
+            "responses": {
+                "200": { "description": "The created job.", "content": json_content(&preview_job) },
+                "401": unauthorized,
+                "409": { "description": "The idempotency key was used for a different request, or that request is still in progress." },
+                "429": too_many_requests,
+            },
+        },
+    }));
+    paths.insert("/preview/sync".to_string(), json!({
+        "post": {
+            "tags": ["preview"],
+            "summary": "Preview a small document synchronously",
+            "parameters": sync_flags,
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/pdf": { "schema": { "type": "string", "format": "binary" } },
+                    "multipart/form-data": { "schema": { "type": "object", "properties": { "file": { "type": "string", "format": "binary" } } } },
+                },
+            },
+            "responses": {
+                "200": { "description": "The finished job.", "content": json_content(&preview_job) },
+                "401": unauthorized,
+                "413": { "description": "The document is too large, use `POST /preview` instead." },
+                "422": { "description": "The job failed.", "content": json_content(&preview_job) },
+                "429": too_many_requests,
+                "503": { "description": "No preview worker available." },
+                "504": { "description": "The preview did not finish in time, follow the job link of the message." },
+            },
+        },
+    }));
+    paths.insert(preview_job_path, preview_job_item);
+    paths.insert(preview_events_path, preview_events_item);
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": NAME, "version": VERSION },
+        "tags": [{ "name": "root" }, { "name": "transform" }, { "name": "preview" }],
+        "paths": paths,
+        "components": {
+            "schemas": generator.take_definitions(),
+            "securitySchemes": {
+                "apiKey": { "type": "apiKey", "in": "header", "name": API_KEY_HEADER },
+            },
+        },
+        "security": [{ "apiKey": [] }, {}],
+    })
+}
@@ -1,51 +1,126 @@
-use axum::extract::{Path, Query};
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Query};
+use axum::http::{HeaderMap, Request};
 use axum::{
     extract::State,
     response::IntoResponse,
     routing::{get, post},
 };
-use axum::{Json, Router};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Result;
+use axum::middleware;
+use axum::{Extension, Json, Router};
 use chrono::Utc;
-use common::dtos::CreatePreviewJobDto;
-use common::models::{PreviewJobModel, PreviewInput, JobStatus};
-use common::util::random;
+use common::dtos::{CreatePreviewJobDto, CreateSyncPreviewJobDto, GetSelfRoute, PreviewJobDto};
+use common::metrics::JOBS_CREATED;
+use common::models::{PreviewJobModel, PreviewInput, PreviewResult, JobStatus};
+use common::nats::request::RequestError;
+use common::util::{random, request_hash::RequestHasher};
 use reqwest::StatusCode;
+use futures::Stream;
 use std::collections::HashMap;
 
 use crate::state::Services;
 
+use super::{admit_job, authenticate, claim_idempotency_key, idempotency_key, repeated_job, Idempotency, can_access, is_multipart, job_events, purge_job, require_caller, seal_callback_headers, Caller};
+
 
 pub fn create_route(services: Services) -> Router {
+    let sync_max_bytes = services.preview_request_service.max_payload();
     Router::new()
-        .route("/preview/:job_id", get(preview_job))
+        .route("/preview/:job_id", get(preview_job).delete(delete_preview_job))
+        .route("/preview/:job_id/events", get(preview_job_events))
         .route("/preview", post(create_preview_job))
+        .route("/preview/sync", post(create_sync_preview_job).layer(DefaultBodyLimit::max(sync_max_bytes)))
+        .route_layer(middleware::from_fn_with_state(services.clone(), authenticate))
         .with_state(services)
 }
 
-#[tracing::instrument(skip(params, services))]
-pub async fn preview_job(State(services): State<Services>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
-    let token = params.get("token").map(|token| token as &str).unwrap_or("wrong_token");
+#[tracing::instrument(skip(params, services, caller))]
+pub async fn preview_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
     if let Ok(Some(job)) = services.job_persistence.get(&job_id).await {
         let job = PreviewJobModel::from_json_slice(&job).unwrap();
-        if job.token == token {
+        if can_access(&caller, &params, &job.token, &job.tenant) {
             return Ok(Json(job.to_dto()))
         }
     }
     Err(StatusCode::NOT_FOUND)
 }
 
-pub async fn create_preview_job(State(services): State<Services>, Json(create_job): Json<CreatePreviewJobDto>) -> impl IntoResponse {
+#[tracing::instrument(skip(params, services, caller))]
+pub async fn preview_job_events(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, StatusCode> {
+    if let Ok(Some(job)) = services.job_persistence.get(&job_id).await {
+        let job = PreviewJobModel::from_json_slice(&job).unwrap();
+        if can_access(&caller, &params, &job.token, &job.tenant) {
+            let updates = services.job_persistence.watch(&job_id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            return Ok(Sse::new(job_events::<PreviewInput, PreviewResult>(updates)).keep_alive(KeepAlive::default()))
+        }
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+#[tracing::instrument(skip(params, services, caller))]
+pub async fn delete_preview_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
+    // a worker may start or finish the job between reading and writing it, then the update fails and it is re-checked
+    while let Ok(Some(entry)) = services.job_persistence.get_entry(&job_id).await {
+        let mut job = PreviewJobModel::from_json_slice(&entry.value).unwrap();
+        if can_access(&caller, &params, &job.token, &job.tenant) {
+            let purge = params.get("purge").map(|purge| purge == "true").unwrap_or(false);
+            let running = matches!(job.status, JobStatus::Pending | JobStatus::InProgress);
+            if purge {
+                if running {
+                    return Err(StatusCode::CONFLICT)
+                }
+                purge_job(&services, &job.id, &job.files).await?;
+                return Ok(StatusCode::NO_CONTENT.into_response())
+            }
+            if !running {
+                return Err(StatusCode::CONFLICT)
+            }
+            job.status = JobStatus::Cancelled;
+            job.finished = Some(Utc::now());
+            match services.job_persistence.update(&job, entry.revision).await {
+                Ok(Some(_)) => return Ok(Json(job.to_dto()).into_response()),
+                Ok(None) => continue,
+                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        break;
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+pub async fn create_preview_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, headers: HeaderMap, Json(create_job): Json<CreatePreviewJobDto>) -> Result<Json<PreviewJobDto>> {
+    let caller = require_caller(&services, caller)?;
+    let idempotency_key = idempotency_key(&headers)?;
+    let mut request_hasher = RequestHasher::new("/preview");
+    request_hasher.update_json(&create_job).map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    let callback_headers = seal_callback_headers(&services, create_job.callback_headers)?;
     let id = random::generate_30_alphanumeric();
+    let idempotency = claim_idempotency_key(&services, &caller, idempotency_key, &id, request_hasher.finish()).await?;
+    if let Idempotency::Repeated(job_id) = &idempotency {
+        return Ok(repeated_job::<PreviewInput, PreviewResult>(&services, job_id).await?)
+    }
+    if let Err(err) = admit_job(&services, &caller, &id, 0).await {
+        idempotency.release(&services).await;
+        return Err(err)
+    }
     let token = random::generate_30_alphanumeric();
     let job = PreviewJobModel {
         id: id.clone(),
         token,
+        tenant: caller.as_ref().map(|caller| caller.tenant.clone()),
+        api_key_id: caller.map(|caller| caller.api_key_id),
         created: Utc::now(),
+        started: None,
+        finished: None,
         status: JobStatus::Pending,
         message: None,
+        error: None,
         callback_uri: create_job.callback_uri,
+        callback_headers,
         input: PreviewInput {
-            source_uri: create_job.source_uri,
+            source_uri: Some(create_job.source_uri),
             source_mime_type: create_job.source_mime_type,
             pdf: create_job.pdf.unwrap_or(true),
             png: create_job.png.unwrap_or(true),
@@ -53,12 +128,89 @@ pub async fn create_preview_job(State(services): State<Services>, Json(create_jo
             signatures: create_job.signatures.unwrap_or(true),
         },
         result: None,
+        files: Vec::new(),
+        progress: None,
+        callback: None,
     };
     if let Err(e) = services.job_persistence.put(&job).await {
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, e))
+        idempotency.release(&services).await;
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
     }
     match services.preview_publish_service.publish(&job.id).await {
-        Ok(_) => Ok(Json(job.to_dto())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e)),
+        Ok(_) => {
+            JOBS_CREATED.with_label_values(&["preview"]).inc();
+            Ok(Json(job.to_dto()))
+        }
+        Err(e) => {
+            idempotency.release(&services).await;
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
+        }
+    }
+}
+
+#[tracing::instrument(skip(services, caller, request))]
+pub async fn create_sync_preview_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Query(create_job): Query<CreateSyncPreviewJobDto>, request: Request<Body>) -> Result<(StatusCode, Json<PreviewJobDto>)> {
+    let caller = require_caller(&services, caller)?;
+    let source_file = read_source_file(request).await?;
+    let id = random::generate_30_alphanumeric();
+    admit_job(&services, &caller, &id, source_file.len() as u64).await?;
+    let token = random::generate_30_alphanumeric();
+    let job = PreviewJobModel {
+        id: id.clone(),
+        token,
+        tenant: caller.as_ref().map(|caller| caller.tenant.clone()),
+        api_key_id: caller.map(|caller| caller.api_key_id),
+        created: Utc::now(),
+        started: None,
+        finished: None,
+        status: JobStatus::Pending,
+        message: None,
+        error: None,
+        callback_uri: None,
+        callback_headers: None,
+        input: PreviewInput {
+            source_uri: None,
+            source_mime_type: None,
+            pdf: create_job.pdf.unwrap_or(true),
+            png: create_job.png.unwrap_or(true),
+            attachments: create_job.attachments.unwrap_or(true),
+            signatures: create_job.signatures.unwrap_or(true),
+        },
+        result: None,
+        files: Vec::new(),
+        progress: None,
+        callback: None,
+    };
+    if let Err(e) = services.job_persistence.put(&job).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
+    }
+    JOBS_CREATED.with_label_values(&["preview"]).inc();
+    let response = services.preview_request_service.request(&job.id, source_file).await.map_err(|err| match err {
+        RequestError::TooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Document too large for synchronous preview, use POST /preview instead.".to_string()),
+        RequestError::TimedOut => (StatusCode::GATEWAY_TIMEOUT, format!("Synchronous preview did not finish in time, follow {}.", job.get_self_route())),
+        RequestError::NoResponders => (StatusCode::SERVICE_UNAVAILABLE, "No preview worker available.".to_string()),
+        RequestError::Failed(err) => (StatusCode::INTERNAL_SERVER_ERROR, err),
+    })?;
+    let dto: PreviewJobDto = serde_json::from_slice(&response).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "job is not valid json".to_string()))?;
+    match dto.status {
+        JobStatus::Finished => Ok((StatusCode::OK, Json(dto))),
+        _ => Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(dto))),
+    }
+}
+
+async fn read_source_file(request: Request<Body>) -> Result<Bytes, (StatusCode, String)> {
+    let too_large = |status: StatusCode, text: String| match status {
+        StatusCode::PAYLOAD_TOO_LARGE => (status, "Document too large for synchronous preview, use POST /preview instead.".to_string()),
+        _ => (status, text),
+    };
+    if is_multipart(&request) {
+        let mut multipart = Multipart::from_request(request, &()).await.map_err(|err| too_large(err.status(), err.body_text()))?;
+        while let Some(field) = multipart.next_field().await.map_err(|err| too_large(err.status(), err.body_text()))? {
+            if field.file_name().is_some() {
+                return field.bytes().await.map_err(|err| too_large(err.status(), err.body_text()));
+            }
+        }
+        return Err((StatusCode::BAD_REQUEST, "Multipart body contains no file.".to_string()));
     }
+    Bytes::from_request(request, &()).await.map_err(|err| too_large(err.status(), err.body_text()))
 }
@@ -1,11 +1,18 @@
-use common::dtos::{RootDto, RootLinks};
+use common::dtos::{ReadinessDto, RootDto, RootLinks};
 use common::util::consts::{NAME, VERSION};
 use axum::http::StatusCode;
+use axum::extract::State;
 use axum::routing::get;
 use axum::{Json, Router};
 
-pub fn create_route() -> Router {
-    Router::new().route("/", get(root_links)).route("/health", get(health))
+use crate::state::Services;
+
+pub fn create_route(services: Services) -> Router {
+    Router::new()
+        .route("/", get(root_links))
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .with_state(services)
 }
 
 pub async fn root_links() -> Result<Json<RootDto>, &'static str> {
@@ -15,6 +22,8 @@ pub async fn root_links() -> Result<Json<RootDto>, &'static str> {
         _links: RootLinks {
             transform: "/transform",
             preview: "/preview",
+            openapi: "/openapi.json",
+            docs: "/docs",
         },
     }))
 }
@@ -23,3 +32,14 @@ pub async fn root_links() -> Result<Json<RootDto>, &'static str> {
 pub async fn health() -> StatusCode {
     StatusCode::OK
 }
+
+/// Unlike `/health` this checks the dependencies, so it answers 503 while NATS or the job bucket are unavailable.
+#[tracing::instrument(skip(services))]
+pub async fn ready(State(services): State<Services>) -> (StatusCode, Json<ReadinessDto>) {
+    let readiness = services.readiness_service.ready().await;
+    let status = match readiness.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(readiness))
+}
@@ -1,58 +1,266 @@
-use axum::extract::{Path, Query};
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Query};
+use axum::http::Request;
 use axum::{
     extract::State,
     response::IntoResponse,
     routing::{get, post},
 };
-use axum::{Json, Router};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Result;
+use axum::middleware;
+use axum::{Extension, Json, Router};
 use chrono::Utc;
-use common::dtos::CreateTransformJobDto;
-use common::models::{TransformJobModel, JobStatus, TransformInput};
-use common::util::random;
+use common::dtos::{CreateTransformJobDto, InspectResultDto, TransformJobDto, ValidationErrorsDto};
+use common::metrics::JOBS_CREATED;
+use common::models::{TransformJobModel, JobStatus, InspectInput, TransformInput, TransformResult, SourceFile};
+use common::nats::request::RequestError;
+use common::persistence::from_internal_uri;
+use common::util::{random, mime::get_content_type, request_hash::RequestHasher};
 use reqwest::StatusCode;
+use futures::Stream;
 use std::collections::HashMap;
 
 use crate::state::Services;
 
+use super::{admit_job, authenticate, claim_idempotency_key, idempotency_key, repeated_job, Idempotency, can_access, is_multipart, job_events, purge_job, require_caller, seal_callback_headers, Caller};
+
 
 pub fn create_route(services: Services) -> Router {
-    Router::new().route("/transform/:job_id", get(transform_job)).route("/transform", post(create_transform_job)).with_state(services)
+    let upload_max_bytes = services.upload_max_bytes;
+    Router::new()
+        .route("/transform/:job_id", get(transform_job).delete(delete_transform_job))
+        .route("/transform/:job_id/events", get(transform_job_events))
+        .route("/transform", post(create_transform_job).layer(DefaultBodyLimit::max(upload_max_bytes)))
+        .route("/transform/inspect", post(inspect_transform_job))
+        .route_layer(middleware::from_fn_with_state(services.clone(), authenticate))
+        .with_state(services)
 }
 
-#[tracing::instrument(skip(params, services))]
-pub async fn transform_job(State(services): State<Services>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
-    let token = params.get("token").map(|token| token as &str).unwrap_or("wrong_token");
+#[tracing::instrument(skip(params, services, caller))]
+pub async fn transform_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
     if let Ok(Some(job)) = services.job_persistence.get(&job_id).await {
         let job = TransformJobModel::from_json_slice(&job).unwrap();
-        if job.token == token {
+        if can_access(&caller, &params, &job.token, &job.tenant) {
             return Ok(Json(job.to_dto()))
         }
     }
     Err(StatusCode::NOT_FOUND)
 }
 
-#[tracing::instrument(skip(services, create_job))]
-pub async fn create_transform_job(State(services): State<Services>, Json(create_job): Json<CreateTransformJobDto>) -> impl IntoResponse {
+#[tracing::instrument(skip(params, services, caller))]
+pub async fn transform_job_events(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, StatusCode> {
+    if let Ok(Some(job)) = services.job_persistence.get(&job_id).await {
+        let job = TransformJobModel::from_json_slice(&job).unwrap();
+        if can_access(&caller, &params, &job.token, &job.tenant) {
+            let updates = services.job_persistence.watch(&job_id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            return Ok(Sse::new(job_events::<TransformInput, TransformResult>(updates)).keep_alive(KeepAlive::default()))
+        }
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+#[tracing::instrument(skip(params, services, caller))]
+pub async fn delete_transform_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Path(job_id): Path<String>, Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
+    // a worker may start or finish the job between reading and writing it, then the update fails and it is re-checked
+    while let Ok(Some(entry)) = services.job_persistence.get_entry(&job_id).await {
+        let mut job = TransformJobModel::from_json_slice(&entry.value).unwrap();
+        if can_access(&caller, &params, &job.token, &job.tenant) {
+            let purge = params.get("purge").map(|purge| purge == "true").unwrap_or(false);
+            let running = matches!(job.status, JobStatus::Pending | JobStatus::InProgress);
+            if purge {
+                if running {
+                    return Err(StatusCode::CONFLICT)
+                }
+                purge_job(&services, &job.id, &job.files).await?;
+                return Ok(StatusCode::NO_CONTENT.into_response())
+            }
+            if !running {
+                return Err(StatusCode::CONFLICT)
+            }
+            job.status = JobStatus::Cancelled;
+            job.finished = Some(Utc::now());
+            match services.job_persistence.update(&job, entry.revision).await {
+                Ok(Some(_)) => return Ok(Json(job.to_dto()).into_response()),
+                Ok(None) => continue,
+                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        break;
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+#[tracing::instrument(skip(services, caller, request))]
+pub async fn create_transform_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, request: Request<Body>) -> Result<Json<TransformJobDto>> {
+    let caller = require_caller(&services, caller)?;
+    let idempotency_key = idempotency_key(request.headers())?;
     let id = random::generate_30_alphanumeric();
     let token = random::generate_30_alphanumeric();
+    let (create_job, files, uploaded_bytes, request_hash) = match is_multipart(&request) {
+        true => read_multipart_job(&services, &id, request).await?,
+        false => {
+            let create_job = Json::<CreateTransformJobDto>::from_request(request, &()).await.map_err(|err| (err.status(), err.body_text()))?.0;
+            validate_source_uris(&create_job)?;
+            let mut request_hasher = RequestHasher::new("/transform");
+            request_hasher.update_json(&create_job).map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))?;
+            (create_job, Vec::new(), 0, request_hasher.finish())
+        }
+    };
+    let errors = create_job.validate();
+    if !errors.is_empty() {
+        discard_uploads(&services, &files).await;
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(ValidationErrorsDto { errors })).into())
+    }
+    let callback_headers = match seal_callback_headers(&services, create_job.callback_headers) {
+        Ok(callback_headers) => callback_headers,
+        Err(err) => {
+            discard_uploads(&services, &files).await;
+            return Err(err.into())
+        }
+    };
+    let idempotency = match claim_idempotency_key(&services, &caller, idempotency_key, &id, request_hash).await {
+        Ok(Idempotency::Repeated(job_id)) => {
+            discard_uploads(&services, &files).await;
+            return Ok(repeated_job::<TransformInput, TransformResult>(&services, &job_id).await?)
+        }
+        Ok(idempotency) => idempotency,
+        Err(err) => {
+            discard_uploads(&services, &files).await;
+            return Err(err.into())
+        }
+    };
+    if let Err(err) = admit_job(&services, &caller, &id, uploaded_bytes).await {
+        discard_uploads(&services, &files).await;
+        idempotency.release(&services).await;
+        return Err(err)
+    }
     let job = TransformJobModel {
         id: id.clone(),
         token,
+        tenant: caller.as_ref().map(|caller| caller.tenant.clone()),
+        api_key_id: caller.map(|caller| caller.api_key_id),
         created: Utc::now(),
+        started: None,
+        finished: None,
         status: JobStatus::Pending,
         message: None,
+        error: None,
         callback_uri: create_job.callback_uri,
+        callback_headers,
         input: TransformInput {
             source_files: create_job.source_files,
             documents: create_job.documents,
         },
         result: None,
+        files,
+        progress: None,
+        callback: None,
     };
     if let Err(e) = services.job_persistence.put(&job).await {
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, e))
+        idempotency.release(&services).await;
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
     }
     match services.transform_publish_service.publish(&job.id).await {
-        Ok(_) => Ok(Json(job.to_dto())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e)),
+        Ok(_) => {
+            JOBS_CREATED.with_label_values(&["transform"]).inc();
+            Ok(Json(job.to_dto()))
+        }
+        Err(e) => {
+            idempotency.release(&services).await;
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
+        }
+    }
+}
+
+/// Downloads and opens the sources like a transform would, but only reports page counts and range errors.
+#[tracing::instrument(skip(services, caller, create_job))]
+pub async fn inspect_transform_job(State(services): State<Services>, Extension(caller): Extension<Option<Caller>>, Json(create_job): Json<CreateTransformJobDto>) -> Result<Json<InspectResultDto>> {
+    let caller = require_caller(&services, caller)?;
+    validate_source_uris(&create_job)?;
+    let errors = create_job.validate();
+    if !errors.is_empty() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(ValidationErrorsDto { errors })).into())
+    }
+    let input = InspectInput {
+        tenant: caller.as_ref().map(|caller| caller.tenant.clone()),
+        input: TransformInput {
+            source_files: create_job.source_files,
+            documents: create_job.documents,
+        },
+    };
+    let payload = serde_json::to_vec(&input).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "inspection is not valid json".to_string()))?;
+    let id = random::generate_30_alphanumeric();
+    admit_job(&services, &caller, &id, 0).await?;
+    let response = services.inspect_request_service.request(&id, payload.into()).await.map_err(|err| match err {
+        RequestError::TooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Inspection request too large.".to_string()),
+        RequestError::TimedOut => (StatusCode::GATEWAY_TIMEOUT, "Inspection did not finish in time.".to_string()),
+        RequestError::NoResponders => (StatusCode::SERVICE_UNAVAILABLE, "No transform worker available.".to_string()),
+        RequestError::Failed(err) => (StatusCode::INTERNAL_SERVER_ERROR, err),
+    })?;
+    let result: InspectResultDto = serde_json::from_slice(&response).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "inspection is not valid json".to_string()))?;
+    Ok(Json(result))
+}
+
+async fn discard_uploads(services: &Services, files: &[String]) {
+    for key in files {
+        _ = services.file_storage.delete_file(key).await;
+    }
+}
+
+async fn read_multipart_job(services: &Services, job_id: &str, request: Request<Body>) -> Result<(CreateTransformJobDto, Vec<String>, u64, String), (StatusCode, String)> {
+    let mut multipart = Multipart::from_request(request, &()).await.map_err(|err| (err.status(), err.body_text()))?;
+    let mut create_job: Option<CreateTransformJobDto> = None;
+    let mut uploads: Vec<(String, String, Bytes)> = Vec::new();
+    let mut uploaded_bytes = 0;
+    let mut request_hasher = RequestHasher::new("/transform");
+    while let Some(field) = multipart.next_field().await.map_err(|err| (err.status(), err.body_text()))? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "job" {
+            let bytes = field.bytes().await.map_err(|err| (err.status(), err.body_text()))?;
+            create_job = Some(serde_json::from_slice(&bytes).map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, format!("Job part is not valid: {}", err)))?);
+            continue;
+        }
+        let content_type = get_content_type(field.content_type(), field.file_name().unwrap_or_default()).to_string();
+        let bytes = field.bytes().await.map_err(|err| (err.status(), err.body_text()))?;
+        uploaded_bytes += bytes.len() as u64;
+        request_hasher.update_upload(&name, &bytes);
+        uploads.push((name, content_type, bytes));
+    }
+    let mut create_job = create_job.ok_or((StatusCode::BAD_REQUEST, "Multipart body contains no job part.".to_string()))?;
+    validate_source_uris(&create_job)?;
+    request_hasher.update_json(&create_job).map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    // uploads are only stored once the job part is valid, so none of the early returns above leave objects behind
+    let mut files: Vec<String> = Vec::with_capacity(uploads.len());
+    for (name, content_type, bytes) in uploads {
+        let key = format!("{}-source-{}", job_id, &name);
+        let uri = match services.file_storage.store_source_file(&key, bytes.to_vec()).await {
+            Ok(uri) => uri,
+            Err(err) => {
+                discard_uploads(services, &files).await;
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+            }
+        };
+        files.push(key);
+        match create_job.source_files.iter_mut().find(|source_file| source_file.id == name) {
+            Some(source_file) => {
+                source_file.uri = uri;
+                source_file.content_type = source_file.content_type.take().or(Some(content_type));
+            }
+            None => create_job.source_files.push(SourceFile {
+                id: name,
+                uri,
+                content_type: Some(content_type),
+            }),
+        }
+    }
+    Ok((create_job, files, uploaded_bytes, request_hasher.finish()))
+}
+
+fn validate_source_uris(create_job: &CreateTransformJobDto) -> Result<(), (StatusCode, String)> {
+    match create_job.source_files.iter().find(|source_file| from_internal_uri(&source_file.uri).is_some()) {
+        Some(source_file) => Err((StatusCode::BAD_REQUEST, format!("Source file '{}' has an internal uri.", source_file.id))),
+        None => Ok(()),
     }
 }
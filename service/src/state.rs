@@ -1,22 +1,52 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use common::{nats::publish::{PublishService, IPublishService}, util::state::{NatsBaseServiceCollection, NatsBaseSettings}, persistence::IJobPersistence};
+use common::{health::ReadinessService, nats::{publish::{PublishService, IPublishService}, request::{IRequestService, RequestService}, dlq_admin::{IDLQAdminService, DLQAdminService}}, models::QuotaModel, nats::{idempotency::{IIdempotencyService, IdempotencyService}, quota::IQuotaService}, util::{cipher::Cipher, state::{NatsBaseSettings, S3BaseSettings, StorageBaseServiceCollection}}, persistence::{IApiKeyPersistence, IJobPersistence, IFileStorage}};
 
 pub type Services = Arc<ServiceCollection>;
 
 pub struct ServiceCollection {
     pub transform_publish_service: Arc<dyn IPublishService>,
     pub preview_publish_service: Arc<dyn IPublishService>,
+    pub preview_request_service: Arc<dyn IRequestService>,
+    pub inspect_request_service: Arc<dyn IRequestService>,
     pub job_persistence: Arc<dyn IJobPersistence>,
+    pub file_storage: Arc<dyn IFileStorage>,
+    pub api_key_persistence: Arc<dyn IApiKeyPersistence>,
+    pub quota_service: Arc<dyn IQuotaService>,
+    pub idempotency_service: Arc<dyn IIdempotencyService>,
+    pub dlq_admin_service: Arc<dyn IDLQAdminService>,
+    pub readiness_service: Arc<ReadinessService>,
+    pub upload_max_bytes: usize,
+    pub admin_token: Option<String>,
+    pub require_api_key: bool,
+    pub default_quota: QuotaModel,
+    pub callback_headers_cipher: Option<Arc<Cipher>>,
 }
 
 impl ServiceCollection {
-    pub async fn build(settings: NatsBaseSettings<'_>, stream: String) -> Result<Arc<Self>, &'static str> {
-        let base = NatsBaseServiceCollection::build(&settings).await?;
+    #[allow(clippy::too_many_arguments)]
+    pub async fn build(settings: NatsBaseSettings<'_>, s3_settings: S3BaseSettings, stream: String, sync_timeout: Duration, sync_max_bytes: usize, upload_max_bytes: usize, idempotency_bucket: String, idempotency_window: Duration, admin_token: Option<String>, require_api_key: bool, default_quota: QuotaModel, callback_headers_cipher: Option<Cipher>) -> Result<Arc<Self>, &'static str> {
+        let base = StorageBaseServiceCollection::build(&settings, s3_settings).await?;
         Ok(Arc::new(ServiceCollection{
             transform_publish_service: Arc::new(PublishService::new(base.base_jetstream.clone(), format!("{}.transform", &stream))),
             preview_publish_service: Arc::new(PublishService::new(base.base_jetstream.clone(), format!("{}.preview", &stream))),
+            preview_request_service: Arc::new(RequestService::new(base.base_jetstream.clone(), format!("{}.preview.sync", &stream), sync_timeout, sync_max_bytes)),
+            inspect_request_service: Arc::new(RequestService::new(base.base_jetstream.clone(), format!("{}.transform.inspect", &stream), sync_timeout, sync_max_bytes)),
             job_persistence: base.job_persistence.clone(),
+            file_storage: base.file_storage.clone(),
+            api_key_persistence: base.api_key_persistence.clone(),
+            quota_service: base.quota_service.clone(),
+            idempotency_service: Arc::new(IdempotencyService::build(base.base_jetstream.clone(), idempotency_bucket, idempotency_window).await?),
+            dlq_admin_service: Arc::new(DLQAdminService::new(base.base_jetstream.clone(), stream)),
+            readiness_service: Arc::new(ReadinessService::new(vec![
+                ("nats", base.base_jetstream.clone()),
+                ("jobBucket", base.job_bucket_check.clone()),
+            ])),
+            upload_max_bytes,
+            admin_token,
+            require_api_key,
+            default_quota,
+            callback_headers_cipher: callback_headers_cipher.map(Arc::new),
         }))
     }
 }
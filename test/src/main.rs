@@ -1,6 +1,13 @@
-use axum::{Router, routing::post, Json, response::IntoResponse, http::StatusCode};
+use axum::{Router, routing::post, response::IntoResponse, http::{HeaderMap, StatusCode}, body::Bytes};
 use common::dtos::{TransformJobDto, PreviewJobDto};
+use common::util::signature::{verify, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use serde::de::DeserializeOwned;
+use std::env;
+use std::fmt::Debug;
 use std::net::{SocketAddr, IpAddr, Ipv6Addr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static MAX_TIMESTAMP_SKEW_SECONDS: i64 = 5 * 60;
 
 #[tokio::main]
 async fn main() {
@@ -16,12 +23,39 @@ async fn main() {
         .unwrap();
 }
 
-pub async fn transform_callback(Json(content): Json<TransformJobDto>) -> impl IntoResponse {
-    println!("{:?}", content);
-    StatusCode::OK
+pub async fn transform_callback(headers: HeaderMap, body: Bytes) -> impl IntoResponse {
+    receive::<TransformJobDto>(&headers, &body)
+}
+
+pub async fn preview_callback(headers: HeaderMap, body: Bytes) -> impl IntoResponse {
+    receive::<PreviewJobDto>(&headers, &body)
 }
 
-pub async fn preview_callback(Json(content): Json<PreviewJobDto>) -> impl IntoResponse {
-    println!("{:?}", content);
-    StatusCode::OK
-}
\ No newline at end of file
+fn receive<Dto: DeserializeOwned + Debug>(headers: &HeaderMap, body: &[u8]) -> StatusCode {
+    if let Ok(secret) = env::var("CALLBACK_SECRET") {
+        if let Err(err) = verify_signature(&secret, headers, body) {
+            println!("rejected callback: {}", err);
+            return StatusCode::UNAUTHORIZED;
+        }
+    }
+    match serde_json::from_slice::<Dto>(body) {
+        Ok(content) => {
+            println!("{:?}", content);
+            StatusCode::OK
+        }
+        Err(_) => StatusCode::BAD_REQUEST,
+    }
+}
+
+fn verify_signature(secret: &str, headers: &HeaderMap, body: &[u8]) -> Result<(), &'static str> {
+    let timestamp = headers.get(TIMESTAMP_HEADER).and_then(|timestamp| timestamp.to_str().ok()).and_then(|timestamp| timestamp.parse::<i64>().ok()).ok_or("missing timestamp")?;
+    let signature = headers.get(SIGNATURE_HEADER).and_then(|signature| signature.to_str().ok()).ok_or("missing signature")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| "clock error")?.as_secs() as i64;
+    if (now - timestamp).abs() > MAX_TIMESTAMP_SKEW_SECONDS {
+        return Err("timestamp too old");
+    }
+    match verify(secret, timestamp, body, signature) {
+        true => Ok(()),
+        false => Err("invalid signature"),
+    }
+}
@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
-use common::convert::BaseConvertService;
+use common::convert::{BaseConvertService, ProgressReporter};
 use common::download::{IDownloadService, DownloadedSourceFile};
-use common::models::TransformJobModel;
-use common::nats::subscribe::{WorkError, IWorkerService};
-use common::persistence::tempfiles::TempJobFileProvider;
+use common::models::{SourceFile, TransformJobModel};
+use common::nats::subscribe::{WorkError, IWorkerService, IHeartbeat, Delivery};
+use common::persistence::{from_internal_uri, tempfiles::TempJobFileProvider};
 use tracing::info;
 
 use crate::transform::ITransformService;
@@ -17,34 +17,71 @@ pub struct ConvertService {
 
 #[async_trait::async_trait]
 impl IWorkerService for ConvertService {
-    #[tracing::instrument(skip(self))]
-    async fn work(&self, job_id: &str) -> Result<(), WorkError> {
+    #[tracing::instrument(skip(self, heartbeat))]
+    async fn work(&self, job_id: &str, delivery: Delivery, heartbeat: Arc<dyn IHeartbeat>) -> Result<(), WorkError> {
         info!("Starting job");
-        let job_model = self.base.job_persistence.get(&job_id).await;
-        if let Ok(Some(job_model)) = job_model {
-            let mut job_model = TransformJobModel::from_json_slice(&job_model).map_err(|_| WorkError::NoRetry)?;
-            let client = reqwest::Client::builder().danger_accept_invalid_certs(true).build().unwrap();
-            let job_files = TempJobFileProvider::build(&job_id).await;
-            let source_files = self.download_service.download_source_files(&client, job_model.input.source_files.clone(), &job_files).await;
-            info!("Downloaded all files for job");
-
-            let failed = source_files.iter().find(|source_file| source_file.is_err());
-
-            match failed {
-                None => {
-                    let source_files: Vec<&DownloadedSourceFile> = source_files.iter().map(|source_file| source_file.as_ref().unwrap()).collect();
-                    let results: Result<_, &str> = self.transform_service.get_transformation(&job_id, &job_model.input.documents, source_files, &job_files).await;
-                    match results {
-                        Ok(results) => self.base.ready(&mut job_model, &client, results).await,
-                        Err(err) => self.base.error(&mut job_model, &client, err).await,
-                    };
-                }
-                Some(err) => {
-                    self.base.error(&mut job_model, &client, err.as_ref().err().unwrap()).await;
+        let job_model = match self.base.job_persistence.get(job_id).await {
+            Ok(Some(job_model)) => job_model,
+            Ok(None) => return Ok(()),
+            // the job bucket is not reachable, the message is redelivered with backoff
+            Err(_) => return Err(WorkError::Retry),
+        };
+        let mut job_model = TransformJobModel::from_json_slice(&job_model).map_err(|_| WorkError::NoRetry)?;
+        self.base.start(&mut job_model).await?;
+        let progress = self.base.progress(&job_model, Some(heartbeat));
+        let client = reqwest::Client::builder().danger_accept_invalid_certs(true).build().unwrap();
+        let job_files = TempJobFileProvider::build(job_id).await;
+        let result = self.convert(&mut job_model, &client, &job_files, delivery, progress).await;
+        job_files.clean_up().await;
+        result
+    }
+}
+
+impl ConvertService {
+    async fn convert(&self, job_model: &mut TransformJobModel, client: &reqwest::Client, job_files: &TempJobFileProvider, delivery: Delivery, progress: ProgressReporter) -> Result<(), WorkError> {
+        let source_files = self.download_service.download_source_files(client, job_model.input.source_files.clone(), job_files).await;
+        info!("Downloaded all files for job");
+        self.base.ensure_not_cancelled(&job_model.id).await?;
+
+        let failed = source_files.iter().filter_map(|source_file| source_file.as_ref().err()).min_by_key(|err| err.transient);
+
+        match failed {
+            None => {
+                let source_files: Vec<&DownloadedSourceFile> = source_files.iter().map(|source_file| source_file.as_ref().unwrap()).collect();
+                self.base.count_source_bytes(job_model, downloaded_bytes(&job_model.input.source_files, &source_files).await).await;
+                let results = self.transform_service.get_transformation(&job_model.id, &job_model.input.documents, source_files, job_files, &progress).await;
+                job_model.progress = Some(progress.stop().await);
+                match results {
+                    Ok(results) => self.base.ready(job_model, results).await?,
+                    Err(err) => {
+                        // files stored before the failure stay on the job, also when it is retried or was cancelled
+                        job_model.add_files(err.files);
+                        if delivery.should_retry(&err.error) {
+                            return self.base.retry(job_model, err.error).await;
+                        }
+                        self.base.error(job_model, err.error).await?;
+                    }
+                };
+            }
+            Some(err) => {
+                progress.stop().await;
+                if delivery.should_retry(err) {
+                    return self.base.retry(job_model, err.clone()).await;
                 }
+                self.base.error(job_model, err.clone()).await?;
             }
-            job_files.clean_up().await;
         }
         Ok(())
     }
 }
+
+pub(crate) async fn downloaded_bytes(inputs: &[SourceFile], source_files: &[&DownloadedSourceFile]) -> u64 {
+    let mut downloaded_bytes = 0;
+    for source_file in source_files {
+        let uploaded = inputs.iter().any(|input| input.id == source_file.id && from_internal_uri(&input.uri).is_some());
+        if !uploaded {
+            downloaded_bytes += tokio::fs::metadata(&source_file.path).await.map(|metadata| metadata.len()).unwrap_or(0);
+        }
+    }
+    downloaded_bytes
+}
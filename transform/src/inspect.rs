@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use common::download::{DownloadedSourceFile, IDownloadService};
+use common::models::InspectInput;
+use common::nats::{quota::IQuotaService, reply_subscribe::IReplyWorkerService};
+use common::persistence::tempfiles::TempJobFileProvider;
+use tracing::{error, info};
+
+use crate::{convert::downloaded_bytes, transform::ITransformService};
+
+pub struct InspectService {
+    pub transform_service: Arc<dyn ITransformService>,
+    pub download_service: Arc<dyn IDownloadService>,
+    pub quota_service: Arc<dyn IQuotaService>,
+    pub client: reqwest::Client,
+}
+
+#[async_trait::async_trait]
+impl IReplyWorkerService for InspectService {
+    #[tracing::instrument(skip(self, payload))]
+    async fn work(&self, inspect_id: &str, payload: Bytes) -> Result<Bytes, &'static str> {
+        info!("Starting inspection");
+        let InspectInput { tenant, input } = serde_json::from_slice(&payload).map_err(|_| "inspection is not valid json")?;
+        let job_files = TempJobFileProvider::build(inspect_id).await;
+        let downloaded = self.download_service.download_source_files(&self.client, input.source_files.clone(), &job_files).await;
+        if let Some(tenant) = &tenant {
+            // inspections download like jobs do, so the bytes count against the same daily quota
+            let source_files: Vec<&DownloadedSourceFile> = downloaded.iter().filter_map(|source_file| source_file.as_ref().ok()).collect();
+            let source_bytes = downloaded_bytes(&input.source_files, &source_files).await;
+            if source_bytes > 0 {
+                if let Err(err) = self.quota_service.add_source_bytes(tenant, source_bytes).await {
+                    error!("Could not count source bytes of inspection '{}': {}", inspect_id, err);
+                }
+            }
+        }
+        let result = self.transform_service.get_inspection(&input.documents, input.source_files.into_iter().zip(downloaded).collect()).await;
+        job_files.clean_up().await;
+        let json = serde_json::to_vec(&result).map_err(|_| "inspection is not valid json")?;
+        Ok(json.into())
+    }
+}
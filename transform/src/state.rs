@@ -1,33 +1,61 @@
 use std::{sync::Arc, time::Duration};
 
-use common::{nats::subscribe::{ISubscribeService, SubscribeService}, convert::BaseConvertService, download::DownloadService, persistence::IJobPersistence, util::state::{NatsBaseSettings, S3BaseSettings, StorageBaseServiceCollection}};
-use pdfium_render::prelude::Pdfium;
+use common::{nats::{publish::PublishService, subscribe::{ISubscribeService, SubscribeService}, reply_subscribe::{IReplySubscribeService, ReplySubscribeService}, dlq_subscribe::{IDLQSubscribeService, DLQSubscribeService}}, convert::{BaseConvertService, CallbackService, DeadLetterService}, health::ReadinessService, models::{TransformInput, TransformResult}, download::DownloadService, persistence::IJobPersistence, util::{cipher::Cipher, executor::ThreadExecutor, state::{NatsBaseSettings, S3BaseSettings, StorageBaseServiceCollection}}};
 
-use crate::{convert::ConvertService, transform::TransformService};
+use crate::{convert::ConvertService, inspect::InspectService, transform::{PdfiumTransformer, TransformService}};
 
 pub struct ServiceCollection {
     pub job_persistence: Arc<dyn IJobPersistence>,
     pub subscribe_service: Arc<dyn ISubscribeService>,
+    pub callback_subscribe_service: Arc<dyn ISubscribeService>,
+    pub inspect_subscribe_service: Arc<dyn IReplySubscribeService>,
+    pub dlq_subscribe_service: Arc<dyn IDLQSubscribeService>,
+    pub readiness_service: Arc<ReadinessService>,
 }
 
 impl ServiceCollection {
-    pub async fn build(settings: NatsBaseSettings<'_>, stream: String, subjects: Vec<String>, parallelism: usize, pdfium: Pdfium, s3_settings: S3BaseSettings, consumer: String, filter: Vec<String>, max_deliver: i64, consumer_ack_wait: Duration) -> Result<Self, &'static str> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn build(settings: NatsBaseSettings<'_>, stream: String, subjects: Vec<String>, parallelism: usize, concurrency: usize, pdfium: ThreadExecutor<PdfiumTransformer>, s3_settings: S3BaseSettings, consumer: String, filter: Vec<String>, max_deliver: i64, consumer_ack_wait: Duration, callback_max_age: Duration, callback_secret: Option<String>, callback_headers_cipher: Option<Cipher>, inspect_subject: String) -> Result<Self, &'static str> {
         let base = StorageBaseServiceCollection::build(&settings, s3_settings).await?;
-        let download_service = Arc::new(DownloadService { parallelism });
+        let download_service = Arc::new(DownloadService { parallelism, storage: base.file_storage.clone() });
         let transform = Arc::new(TransformService {
             storage: base.file_storage.clone(),
             pdfium,
         });
+        let callback_subject = format!("{}.{}-callback", &stream, &consumer);
+        let base_convert = Arc::new(BaseConvertService {
+            job_persistence: base.job_persistence.clone(),
+            callback_publish_service: Arc::new(PublishService::new(base.base_jetstream.clone(), callback_subject.clone())),
+            progress_interval: consumer_ack_wait / 3,
+            quota_service: base.quota_service.clone(),
+            job_type: "transform",
+        });
         let worker = ConvertService {
-            base: Arc::new(BaseConvertService {
-                job_persistence: base.job_persistence.clone(),
-            }),
+            base: base_convert.clone(),
+            transform_service: transform.clone(),
+            download_service: download_service.clone(),
+        };
+        let readiness_service = Arc::new(ReadinessService::new(vec![
+            ("nats", base.base_jetstream.clone()),
+            ("jobBucket", base.job_bucket_check.clone()),
+            ("storage", base.file_storage_check.clone()),
+            ("pdfium", transform.clone()),
+        ]));
+        let inspect_worker = InspectService {
             transform_service: transform,
-            download_service: download_service,
+            download_service,
+            quota_service: base.quota_service.clone(),
+            client: reqwest::Client::builder().danger_accept_invalid_certs(true).build().map_err(|_| "could not build http client")?,
         };
+        let dlq_worker = DeadLetterService::<TransformInput, TransformResult>::new(base_convert);
+        let callback_worker = CallbackService::<TransformInput, TransformResult>::new(base.job_persistence.clone(), base.api_key_persistence.clone(), consumer_ack_wait / 2, callback_max_age, callback_secret, callback_headers_cipher.map(Arc::new));
         Ok(ServiceCollection{
-            subscribe_service: Arc::new(SubscribeService::build(base.base_jetstream.clone(), stream, subjects, worker, consumer, filter, max_deliver, consumer_ack_wait).await?),
+            callback_subscribe_service: Arc::new(SubscribeService::build(base.base_jetstream.clone(), stream.clone(), subjects.clone(), callback_worker, format!("{}-callback", &consumer), vec![callback_subject], -1, consumer_ack_wait, concurrency).await?),
+            inspect_subscribe_service: Arc::new(ReplySubscribeService::new(base.base_jetstream.clone(), inspect_subject, consumer.clone(), inspect_worker, concurrency)),
+            subscribe_service: Arc::new(SubscribeService::build(base.base_jetstream.clone(), stream.clone(), subjects, worker, consumer.clone(), filter, max_deliver, consumer_ack_wait, concurrency).await?),
+            dlq_subscribe_service: Arc::new(DLQSubscribeService::build(base.base_jetstream.clone(), stream, dlq_worker, consumer, settings.max_age).await?),
             job_persistence: base.job_persistence.clone(),
+            readiness_service,
         })
     }
 }
@@ -1,11 +1,20 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use common::convert::ProgressReporter;
+use common::dtos::{InspectDocumentDto, InspectResultDto, InspectSourceFileDto, PageSizeDto, ValidationErrorDto};
 use common::download::DownloadedSourceFile;
+use common::error::{Error, ErrorCode};
+use common::health::IReadinessCheck;
+use common::metrics::PDFIUM_DURATION;
 use common::persistence::IFileStorage;
 use common::persistence::tempfiles::TempJobFileProvider;
-use common::models::{Document, Part, Rotation, TransformDocumentResult};
+use common::util::{executor::ThreadExecutor, spill::{ISpill, SpillBytes, SpillSender}};
+use common::models::{Document, Part, Rotation, SourceFile, TransformDocumentResult, StoredError, StoredResult, TransformResult};
 use mime::Mime;
 use pdfium_render::prelude::*;
+use tokio::sync::mpsc;
 use tracing::info;
 
 #[cfg(feature = "static")]
@@ -18,96 +27,304 @@ pub fn init_pdfium() -> Result<Pdfium, &'static str> {
     Ok(Pdfium::new(Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./")).map_err(|_| "Could not init pdfium")?))
 }
 
+pub fn spawn_pdfium() -> Result<ThreadExecutor<PdfiumTransformer>, &'static str> {
+    ThreadExecutor::spawn("pdfium", || Ok(PdfiumTransformer { pdfium: init_pdfium()? }))
+}
+
 #[async_trait::async_trait]
 pub trait ITransformService: Send + Sync {
     async fn get_transformation<'a>(
-        &self, job_id: &str, documents: &Vec<Document>, source_files: Vec<&DownloadedSourceFile>, job_files: &TempJobFileProvider,
-    ) -> Result<Vec<TransformDocumentResult>, &'static str>;
+        &self, job_id: &str, documents: &[Document], source_files: Vec<&DownloadedSourceFile>, job_files: &TempJobFileProvider, progress: &ProgressReporter,
+    ) -> Result<StoredResult<TransformResult>, StoredError>;
+    async fn get_inspection(&self, documents: &[Document], source_files: Vec<(SourceFile, Result<DownloadedSourceFile, Error>)>) -> InspectResultDto;
 }
 
 pub struct TransformService {
     pub storage: Arc<dyn IFileStorage>,
-    pub pdfium: Pdfium,
+    pub pdfium: ThreadExecutor<PdfiumTransformer>,
 }
 
 #[async_trait::async_trait]
 impl ITransformService for TransformService {
     async fn get_transformation<'a>(
-        &self, job_id: &str, documents: &Vec<Document>, source_files: Vec<&DownloadedSourceFile>, job_files: &TempJobFileProvider,
-    ) -> Result<Vec<TransformDocumentResult>, &'static str> {
-        let results: Vec<_> = {
-            let mut cache: Option<(&str, PdfDocument)> = None;
-
-            documents
-                .iter()
-                .map(|document| -> Result<_, &'static str> {
-                    let cache_ref: &mut Option<(&str, PdfDocument)> = &mut cache;
-                    let bytes = {
-                        let mut new_doc = self.pdfium.create_new_pdf().map_err(|_| "Could not create empty document.")?;
-                        for part in &document.parts {
-                            if cache_ref.is_some() && cache_ref.as_ref().unwrap().0.eq(&part.source_file) {
-                                self.add_part(&mut new_doc, &cache_ref.as_ref().unwrap().1, part)?;
-                            } else {
-                                let source_file = source_files.iter().find(|source_file| source_file.id.eq(&part.source_file)).ok_or("Could not find corresponding source file.")?;
-                                if self.is_supported_image(&source_file.content_type) {
-                                    self.add_image(&mut new_doc, &source_file, &part)?;
-                                } else {
-                                    let source_doc = self.pdfium.load_pdf_from_file(&source_file.path, None).map_err(|_| "Could not create document from file.")?;
-                                    info!("source {} has {} pages", &source_file.id, source_doc.pages().len());
-                                    *cache_ref = Some((&part.source_file, source_doc));
-                                    self.add_part(&mut new_doc, &cache_ref.as_ref().unwrap().1, part)?;
-                                }
-                                info!("generated {} has {} pages", &document.id, new_doc.pages().len());
-                            }
-                        }
-                        for attachment in &document.attachments {
-                            let source_file = source_files.iter().find(|source_file| source_file.id.eq(&attachment.source_file)).ok_or("Could not find corresponding source file.")?;
-                            new_doc.attachments_mut().create_attachment_from_file(&attachment.name, &source_file.path).map_err(|_| "Could not add attachment.")?;
-                        }
-                        new_doc.save_to_bytes().map_err(|_| "Could not save file.")?
-                    };
-                    Ok(async move {
-                        info!("generated {} is {} KiB", &document.id, bytes.len() / 1024);
-                        let file_url = self.storage.store_result_file(&format!("{}-{}", &job_id, &document.id), &document.id, Some("application/pdf"), bytes).await?;
-
-                        Ok::<TransformDocumentResult, &'static str>(TransformDocumentResult {
-                            download_url: file_url,
-                            id: document.id.to_string(),
-                        })
-                    })
-                })
-                .collect()
+        &self, job_id: &str, documents: &[Document], source_files: Vec<&DownloadedSourceFile>, job_files: &TempJobFileProvider, progress: &ProgressReporter,
+    ) -> Result<StoredResult<TransformResult>, StoredError> {
+        progress.set_total(documents.len());
+        // documents are uploaded while pdfium generates the next ones, a failed upload drops the receiver and stops the generation
+        let (sender, receiver) = mpsc::channel::<GeneratedDocument>(GENERATED_BUFFER);
+        let generated = {
+            let documents = documents.to_vec();
+            let source_files: Vec<DownloadedSourceFile> = source_files.into_iter().cloned().collect();
+            let sender = SpillSender::new(sender, job_files.directory());
+            self.pdfium.run(move |transformer| transformer.generate_documents(&documents, &source_files, sender))
         };
         let mut document_results = Vec::with_capacity(documents.len());
-        for result in results {
-            let value = result?.await?;
-            document_results.push(value);
+        let mut files = Vec::with_capacity(documents.len());
+        let stored = async {
+            // owned by the upload, so a failed upload closes the channel
+            let mut receiver = receiver;
+            while let Some(document) = receiver.recv().await {
+                self.store_generated(job_id, document, progress, &mut document_results, &mut files).await?;
+            }
+            Ok::<_, Error>(())
+        };
+        let (generated, stored) = tokio::join!(generated, stored);
+        if let Err(error) = stored {
+            return Err(StoredError { error, files });
+        }
+        // a stopped pdfium thread is not the fault of the document, the job is retried and readiness fails
+        let spilled = match generated.map_err(|err| Error::transient(ErrorCode::Executor, "Pdfium did not run the generation.").with_source(err)).and_then(|generated| generated) {
+            Ok(spilled) => spilled,
+            Err(error) => return Err(StoredError { error, files }),
+        };
+        for document in spilled {
+            if let Err(error) = self.store_generated(job_id, document, progress, &mut document_results, &mut files).await {
+                return Err(StoredError { error, files });
+            }
+        }
+        Ok(StoredResult {
+            result: document_results,
+            files,
+        })
+    }
+
+    async fn get_inspection(&self, documents: &[Document], source_files: Vec<(SourceFile, Result<DownloadedSourceFile, Error>)>) -> InspectResultDto {
+        let mut errors = Vec::new();
+        let mut inspected_source_files = Vec::with_capacity(source_files.len());
+        let mut page_counts: HashMap<&str, (u16, bool)> = HashMap::new();
+        for (index, (source_file, downloaded)) in source_files.iter().enumerate() {
+            let inspected = match downloaded {
+                Ok(downloaded) => self.inspect_source_file(downloaded).await,
+                Err(err) => InspectSourceFileDto {
+                    id: source_file.id.clone(),
+                    content_type: source_file.content_type.clone(),
+                    page_count: None,
+                    page_sizes: Vec::new(),
+                    encrypted: None,
+                    error: Some(err.to_string()),
+                },
+            };
+            match (&inspected.error, inspected.page_count, downloaded) {
+                (Some(err), _, _) => errors.push(ValidationErrorDto::new(format!("sourceFiles[{}]", index), err.clone())),
+                (None, Some(page_count), Ok(downloaded)) => {
+                    page_counts.insert(&source_file.id, (page_count, is_supported_image(&downloaded.content_type)));
+                }
+                _ => {}
+            }
+            inspected_source_files.push(inspected);
+        }
+
+        let mut inspected_documents = Vec::with_capacity(documents.len());
+        for (index, document) in documents.iter().enumerate() {
+            let mut page_count = Some(0u32);
+            for (part_index, part) in document.parts.iter().enumerate() {
+                let pages = match page_counts.get(part.source_file.as_str()) {
+                    // an image always becomes a single page, whatever its range
+                    Some((_, true)) => Some(1),
+                    Some((pages, false)) => match part.validate_pages(Some(*pages)) {
+                        Ok(()) => Some((part.end_page_number.unwrap_or(*pages) - part.start_page_number.unwrap_or(1) + 1) as u32),
+                        Err(err) => {
+                            errors.push(ValidationErrorDto::new(format!("documents[{}].parts[{}].{}", index, part_index, err.field()), err.to_string()));
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                page_count = page_count.zip(pages).map(|(page_count, pages)| page_count + pages);
+            }
+            inspected_documents.push(InspectDocumentDto {
+                id: document.id.clone(),
+                page_count,
+            });
+        }
+
+        InspectResultDto {
+            source_files: inspected_source_files,
+            documents: inspected_documents,
+            errors,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IReadinessCheck for TransformService {
+    async fn check(&self) -> Result<(), String> {
+        // jobs queue up behind each other on the pdfium thread, so only its liveness is checked
+        if self.pdfium.is_running() {
+            Ok(())
+        } else {
+            Err("Pdfium thread stopped.".to_string())
         }
-        Ok(document_results)
     }
 }
 
 impl TransformService {
-    fn add_part(&self, new_document: &mut PdfDocument, source_document: &PdfDocument, part: &Part) -> Result<(), &'static str> {
+    async fn store_generated(&self, job_id: &str, document: GeneratedDocument, progress: &ProgressReporter, document_results: &mut Vec<TransformDocumentResult>, files: &mut Vec<String>) -> Result<(), Error> {
+        progress.advance();
+        let bytes = document.bytes.into_bytes().await.map_err(|err| Error::transient(ErrorCode::Storage, "Could not read generated document.").with_source(err).in_document(&document.id))?;
+        let (document_result, key) = self.store_document(job_id, document.id, bytes, progress).await?;
+        document_results.push(document_result);
+        files.push(key);
+        Ok(())
+    }
+
+    async fn store_document(&self, job_id: &str, document_id: String, bytes: Vec<u8>, progress: &ProgressReporter) -> Result<(TransformDocumentResult, String), Error> {
+        progress.ensure_not_cancelled()?;
+        info!("generated {} is {} KiB", &document_id, bytes.len() / 1024);
+        let key = format!("{}-{}", &job_id, &document_id);
+        let file_url = self.storage.store_result_file(&key, &document_id, Some("application/pdf"), bytes).await.map_err(|err| err.in_document(&document_id))?;
+        Ok((TransformDocumentResult {
+            download_url: file_url,
+            id: document_id,
+        }, key))
+    }
+
+    async fn inspect_source_file(&self, source_file: &DownloadedSourceFile) -> InspectSourceFileDto {
+        let mut inspected = InspectSourceFileDto {
+            id: source_file.id.clone(),
+            content_type: Some(source_file.content_type.to_string()),
+            page_count: None,
+            page_sizes: Vec::new(),
+            encrypted: None,
+            error: None,
+        };
+        if is_supported_image(&source_file.content_type) {
+            match image::image_dimensions(&source_file.path) {
+                Ok((width, height)) => {
+                    inspected.page_count = Some(1);
+                    inspected.page_sizes.push(PageSizeDto { width: width as f32, height: height as f32 });
+                    inspected.encrypted = Some(false);
+                }
+                Err(err) => inspected.error = Some(format!("Could not read image '{}' ({}).", &source_file.id, err)),
+            }
+            return inspected;
+        }
+        let path = source_file.path.clone();
+        let pending = inspected.clone();
+        match self.pdfium.run(move |transformer| transformer.inspect_pdf(&path, pending)).await {
+            Ok(inspected) => inspected,
+            Err(err) => InspectSourceFileDto {
+                error: Some(format!("Could not inspect source file '{}' ({}).", &source_file.id, err)),
+                ..inspected
+            },
+        }
+    }
+}
+
+/// Number of generated documents kept in memory for their upload, later ones are spilled to the job directory.
+const GENERATED_BUFFER: usize = 2;
+
+pub struct GeneratedDocument {
+    id: String,
+    bytes: SpillBytes,
+}
+
+impl ISpill for GeneratedDocument {
+    fn spill(self, path: PathBuf) -> Result<Self, &'static str> {
+        Ok(GeneratedDocument {
+            id: self.id,
+            bytes: self.bytes.spill(path)?,
+        })
+    }
+}
+
+/// Owns the pdfium binding on the thread of its executor, pdfium is not thread safe.
+pub struct PdfiumTransformer {
+    pdfium: Pdfium,
+}
+
+impl PdfiumTransformer {
+    /// Never waits for the uploads, so a slow upload does not hold the pdfium thread, returns the documents that had to be spilled.
+    fn generate_documents(&self, documents: &[Document], source_files: &[DownloadedSourceFile], mut sender: SpillSender<GeneratedDocument>) -> Result<Vec<GeneratedDocument>, Error> {
+        let source_files: Vec<&DownloadedSourceFile> = source_files.iter().collect();
+        let mut cache: Option<(&str, PdfDocument)> = None;
+        for document in documents {
+            let bytes = self.generate_document(document, &source_files, &mut cache).map_err(|err| err.in_document(&document.id))?;
+            let generated = GeneratedDocument {
+                id: document.id.clone(),
+                bytes: SpillBytes::Memory(bytes),
+            };
+            sender.send(generated).map_err(|err| Error::transient(ErrorCode::Storage, "Could not hand over generated document.").with_source(err).in_document(&document.id))?;
+        }
+        Ok(sender.into_spilled())
+    }
+
+    fn generate_document<'a>(&'a self, document: &'a Document, source_files: &[&DownloadedSourceFile], cache: &mut Option<(&'a str, PdfDocument<'a>)>) -> Result<Vec<u8>, Error> {
+        let _timer = PDFIUM_DURATION.with_label_values(&["generate_document"]).start_timer();
+        let mut new_doc = self.pdfium.create_new_pdf().map_err(|err| Error::permanent(ErrorCode::InvalidDocument, "Could not create empty document.").with_source(err))?;
+        for (part_index, part) in document.parts.iter().enumerate() {
+            let result = match cache {
+                Some((source_file, source_doc)) if *source_file == part.source_file => self.add_part(&mut new_doc, source_doc, part),
+                _ => {
+                    let source_file = self.find_source_file(source_files, &part.source_file)?;
+                    if is_supported_image(&source_file.content_type) {
+                        self.add_image(&mut new_doc, source_file, part)
+                    } else {
+                        let source_doc = self.pdfium.load_pdf_from_file(&source_file.path, None).map_err(|err| Error::permanent(ErrorCode::InvalidDocument, "Could not create document from file.").with_source(err).in_part(part_index))?;
+                        info!("source {} has {} pages", &source_file.id, source_doc.pages().len());
+                        let (_, source_doc) = cache.insert((&part.source_file, source_doc));
+                        self.add_part(&mut new_doc, source_doc, part)
+                    }
+                }
+            };
+            result.map_err(|err| err.in_part(part_index))?;
+            info!("generated {} has {} pages", &document.id, new_doc.pages().len());
+        }
+        for attachment in &document.attachments {
+            let source_file = self.find_source_file(source_files, &attachment.source_file)?;
+            new_doc.attachments_mut().create_attachment_from_file(&attachment.name, &source_file.path).map_err(|err| Error::permanent(ErrorCode::Attachment, "Could not add attachment.").with_source(err))?;
+        }
+        new_doc.save_to_bytes().map_err(|err| Error::permanent(ErrorCode::Save, "Could not save file.").with_source(err))
+    }
+
+    fn inspect_pdf(&self, path: &Path, mut inspected: InspectSourceFileDto) -> InspectSourceFileDto {
+        let _timer = PDFIUM_DURATION.with_label_values(&["inspect"]).start_timer();
+        match self.pdfium.load_pdf_from_file(path, None) {
+            Ok(source_doc) => {
+                let pages = source_doc.pages();
+                inspected.page_count = Some(pages.len());
+                inspected.page_sizes = pages.iter().map(|page| PageSizeDto { width: page.width().value, height: page.height().value }).collect();
+                inspected.encrypted = Some(!matches!(source_doc.permissions().security_handler_revision(), Ok(PdfSecurityHandlerRevision::Unprotected)));
+            }
+            Err(PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::PasswordError)) => {
+                inspected.encrypted = Some(true);
+                inspected.error = Some(format!("Source file '{}' is password protected.", &inspected.id));
+            }
+            Err(err) => inspected.error = Some(format!("Could not create document from file '{}' ({}).", &inspected.id, err)),
+        }
+        inspected
+    }
+
+    fn find_source_file<'b>(&self, source_files: &[&'b DownloadedSourceFile], id: &str) -> Result<&'b DownloadedSourceFile, Error> {
+        source_files
+            .iter()
+            .find(|source_file| source_file.id.eq(id))
+            .copied()
+            .ok_or_else(|| Error::permanent(ErrorCode::SourceFileNotFound, format!("Could not find corresponding source file '{}'.", id)))
+    }
+
+    fn add_part(&self, new_document: &mut PdfDocument, source_document: &PdfDocument, part: &Part) -> Result<(), Error> {
+        let pages = source_document.pages().len();
+        part.validate_pages(Some(pages)).map_err(|err| Error::permanent(ErrorCode::InvalidPageRange, err.to_string()))?;
         let start_page_number = part.start_page_number.unwrap_or(1);
-        let end_page_number = part.end_page_number.unwrap_or(source_document.pages().len());
-        self.validate_pages(start_page_number, end_page_number, source_document)?;
+        let end_page_number = part.end_page_number.unwrap_or(pages);
 
         let new_start_page_number = new_document.pages().len() + 1;
         let new_end_page_number = new_start_page_number + (end_page_number - start_page_number);
 
         new_document
             .pages_mut()
-            .copy_page_range_from_document(&source_document, start_page_number - 1..=end_page_number - 1, new_start_page_number - 1)
-            .map_err(|_| "Could not transfer pages.")?;
+            .copy_page_range_from_document(source_document, start_page_number - 1..=end_page_number - 1, new_start_page_number - 1)
+            .map_err(|err| Error::permanent(ErrorCode::TransferPages, "Could not transfer pages.").with_source(err))?;
 
         self.turn_pages(new_start_page_number, new_end_page_number, new_document, part)?;
 
         Ok(())
     }
 
-    fn add_image(&self, new_document: &mut PdfDocument, source_file: &DownloadedSourceFile, part: &Part) -> Result<(), &'static str> {
-        let source_img = image::io::Reader::open(&source_file.path).map_err(|_| "")?.with_guessed_format().map_err(|_| "")?.decode().map_err(|_| "")?;
+    fn add_image(&self, new_document: &mut PdfDocument, source_file: &DownloadedSourceFile, part: &Part) -> Result<(), Error> {
+        let invalid_image = |err: &dyn std::fmt::Display| Error::permanent(ErrorCode::InvalidImage, format!("Could not read image '{}'.", &source_file.id)).with_source(err);
+        let source_img = image::io::Reader::open(&source_file.path).map_err(|err| invalid_image(&err))?.with_guessed_format().map_err(|err| invalid_image(&err))?.decode().map_err(|err| invalid_image(&err))?;
 
         let source_img = {
             match &part.rotation {
@@ -121,28 +338,17 @@ impl TransformService {
             }
         };
 
-        let object = PdfPageImageObject::new_with_width(&new_document, &source_img, PdfPoints::new(source_img.width() as f32)).map_err(|_| "")?;
+        let object = PdfPageImageObject::new_with_width(new_document, &source_img, PdfPoints::new(source_img.width() as f32)).map_err(|err| invalid_image(&err))?;
 
         let mut page = new_document
             .pages_mut()
             .create_page_at_end(PdfPagePaperSize::Custom(PdfPoints::new(source_img.width() as f32), PdfPoints::new(source_img.height() as f32)))
-            .map_err(|_| "")?;
-        page.objects_mut().add_image_object(object).map_err(|_| "")?;
+            .map_err(|err| invalid_image(&err))?;
+        page.objects_mut().add_image_object(object).map_err(|err| invalid_image(&err))?;
         Ok(())
     }
 
-    fn validate_pages(&self, start_page_number: u16, end_page_number: u16, source_document: &PdfDocument) -> Result<(), &'static str> {
-        if start_page_number > end_page_number {
-            return Err("Start page number can't be greater than end page number.");
-        }
-        let pages = source_document.pages();
-        if end_page_number > pages.len() {
-            return Err("End page number exceeds pages of document.");
-        }
-        Ok(())
-    }
-
-    fn turn_pages(&self, start_page_number: u16, end_page_number: u16, source_document: &PdfDocument, part: &Part) -> Result<(), &'static str> {
+    fn turn_pages(&self, start_page_number: u16, end_page_number: u16, source_document: &PdfDocument, part: &Part) -> Result<(), Error> {
         if part.rotation.is_some() {
             let part_rotation: i32 = part.rotation.unwrap_or(Rotation::P0).as_degrees();
             let part_rotation: i32 = {
@@ -154,7 +360,7 @@ impl TransformService {
             };
             let pages = source_document.pages();
             for mut page in pages.iter().skip(start_page_number as usize - 1).take(end_page_number as usize - start_page_number as usize + 1) {
-                let rotation = page.rotation().map_err(|_| "Could not get rotation.")?;
+                let rotation = page.rotation().map_err(|err| Error::permanent(ErrorCode::TransferPages, "Could not get rotation.").with_source(err))?;
                 let mut new_rotation = rotation.as_degrees() as i32 + part_rotation;
                 if new_rotation > 360 {
                     new_rotation -= 360;
@@ -171,8 +377,8 @@ impl TransformService {
         }
         Ok(())
     }
+}
 
-    fn is_supported_image(&self, content_type: &Mime) -> bool {
-        content_type.eq(&mime::IMAGE_PNG) || content_type.eq(&mime::IMAGE_JPEG) || content_type.eq(&mime::IMAGE_GIF) || content_type.eq(&mime::IMAGE_BMP)
-    }
+fn is_supported_image(content_type: &Mime) -> bool {
+    content_type.eq(&mime::IMAGE_PNG) || content_type.eq(&mime::IMAGE_JPEG) || content_type.eq(&mime::IMAGE_GIF) || content_type.eq(&mime::IMAGE_BMP)
 }